@@ -6,7 +6,7 @@ use rand::StdRng;
 use tcod::{Console, BackgroundFlag, RootInitializer};
 use tcod::input::Key::{Special, Printable};
 use tcod::input::KeyCode::{Up, Down, Left, Right, Escape};
-use world::{WorldMap, Terrain, Location};
+use world::{WorldMap, Location};
 
 fn main() {
     let width = 80;
@@ -24,20 +24,7 @@ fn main() {
         // Draw world.
         console.clear();
         for (tile, location) in world.tiles() {
-            match tile.terrain {
-                Terrain::Floor => {
-                    console.put_char(location.x, location.y, '.', BackgroundFlag::Set);
-                },
-                Terrain::Wall => {
-                    console.put_char(location.x, location.y, '#', BackgroundFlag::Set);
-                },
-                Terrain::Nothing => {
-                    console.put_char(location.x, location.y, ' ', BackgroundFlag::Set);
-                },
-                Terrain::Debug => {
-                    console.put_char(location.x, location.y, '^', BackgroundFlag::Set);
-                }
-            }
+            console.put_char(location.x, location.y, tile.terrain.to_char(), BackgroundFlag::Set);
         }
 
         // Draw character.
@@ -81,11 +68,8 @@ fn main() {
                 },
                 _ => location
             };
-            match world.get_tile(new_loc).terrain {
-                Terrain::Floor => location = new_loc,
-                Terrain::Debug => location = new_loc,
-                Terrain::Wall => {},
-                Terrain::Nothing => {}
+            if world.can_step(location, new_loc) {
+                location = new_loc;
             }
         }
     }
@@ -0,0 +1,81 @@
+extern crate rand;
+
+use rect::Rect;
+use world_map::{FeatureBuilder, Feature, Location, WorldMap};
+use self::rand::Rng;
+
+impl WorldMap {
+    // Generates a map by binary space partitioning: the map is split in two
+    // over and over until the pieces are too small to split, a room is
+    // carved in each piece, and the rooms on either side of every split are
+    // joined by a tunnel. Rooms never overlap and are always connected.
+    pub fn generate_bsp<R: Rng>(rng: &mut R, width: i32, height: i32,
+                                min_leaf_size: i32) -> (Self, Location) {
+        assert!(min_leaf_size >= 3, "Leaves must fit a room at least 3 tiles across.");
+        assert!(width >= min_leaf_size && height >= min_leaf_size);
+
+        let mut world = WorldMap::new(width, height);
+        let rooms = split(rng, &mut world, Rect::new(0, 0, width, height), min_leaf_size);
+
+        // Pick a random floor in a random room to start on.
+        let room = &rooms[rng.gen_range::<usize>(0, rooms.len())];
+        let starting_loc = room.random_floor(rng);
+
+        (world, starting_loc)
+    }
+}
+
+// Splits a leaf in two and recurses, or carves a room if it's too small to
+// split. Returns the rooms carved inside the leaf.
+fn split<R: Rng>(rng: &mut R, world: &mut WorldMap, leaf: Rect, min_size: i32) -> Vec<Feature> {
+    let can_split_x = leaf.width >= 2 * min_size;
+    let can_split_y = leaf.height >= 2 * min_size;
+    if !can_split_x && !can_split_y {
+        let width = rng.gen_range::<i32>(3, leaf.width + 1);
+        let height = rng.gen_range::<i32>(3, leaf.height + 1);
+        let x = leaf.x + rng.gen_range::<i32>(0, leaf.width - width + 1);
+        let y = leaf.y + rng.gen_range::<i32>(0, leaf.height - height + 1);
+        let room = FeatureBuilder::room(width, height)
+            .location(Location::new(x, y))
+            .build();
+        world.carve_room(room.bounds());
+        return vec![room];
+    }
+
+    // Split across the longer side, unless only the other one fits.
+    let split_x = can_split_x && (!can_split_y || leaf.width >= leaf.height);
+    let (first, second) = if split_x {
+        let at = rng.gen_range::<i32>(min_size, leaf.width - min_size + 1);
+        (Rect::new(leaf.x, leaf.y, at, leaf.height),
+         Rect::new(leaf.x + at, leaf.y, leaf.width - at, leaf.height))
+    } else {
+        let at = rng.gen_range::<i32>(min_size, leaf.height - min_size + 1);
+        (Rect::new(leaf.x, leaf.y, leaf.width, at),
+         Rect::new(leaf.x, leaf.y + at, leaf.width, leaf.height - at))
+    };
+    let mut rooms = split(rng, world, first, min_size);
+    let other_rooms = split(rng, world, second, min_size);
+
+    // Join the halves through a room from each.
+    let from = rooms[rng.gen_range::<usize>(0, rooms.len())].center();
+    let to = other_rooms[rng.gen_range::<usize>(0, other_rooms.len())].center();
+    world.carve_tunnel(from, to);
+
+    rooms.extend(other_rooms);
+    rooms
+}
+
+#[test]
+fn test_generate_bsp_is_connected() {
+    use self::rand::{SeedableRng, StdRng};
+
+    for seed in 0..10 {
+        let seed: &[_] = &[seed];
+        let mut rng: StdRng = SeedableRng::from_seed(seed);
+        let (world, start) = WorldMap::generate_bsp(&mut rng, 80, 50, 8);
+        let regions = world.connected_regions();
+        assert_eq!(regions.len(), 1);
+        assert!(regions[0].contains(&start));
+        assert!(world.rooms().iter().any(|room| room.contains(start)));
+    }
+}
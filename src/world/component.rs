@@ -0,0 +1,111 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::collections::hash_map;
+
+// Gameplay data of one type, such as health or a name, for any number of
+// entities, keyed by entity id.
+pub struct ComponentStore<T> {
+    components: HashMap<u64, T>
+}
+
+impl<T> ComponentStore<T> {
+    pub fn new() -> Self {
+        ComponentStore { components: HashMap::new() }
+    }
+    // Gives an entity the component, returning the one it replaces.
+    pub fn insert(&mut self, id: u64, component: T) -> Option<T> {
+        self.components.insert(id, component)
+    }
+    pub fn get(&self, id: u64) -> Option<&T> {
+        self.components.get(&id)
+    }
+    pub fn get_mut(&mut self, id: u64) -> Option<&mut T> {
+        self.components.get_mut(&id)
+    }
+    pub fn remove(&mut self, id: u64) -> Option<T> {
+        self.components.remove(&id)
+    }
+    pub fn len(&self) -> usize {
+        self.components.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.components.is_empty()
+    }
+    // Every entity id with its component, in no particular order.
+    pub fn iter(&self) -> hash_map::Iter<u64, T> {
+        self.components.iter()
+    }
+}
+
+impl<T> Default for ComponentStore<T> {
+    fn default() -> Self {
+        ComponentStore::new()
+    }
+}
+
+// A ComponentStore of any type, so stores of different types can be kept
+// together and all cleaned up when an entity goes away.
+trait AnyStore {
+    fn remove_entity(&mut self, id: u64);
+    fn as_any(&self) -> &Any;
+    fn as_any_mut(&mut self) -> &mut Any;
+}
+
+impl<T: 'static> AnyStore for ComponentStore<T> {
+    fn remove_entity(&mut self, id: u64) {
+        self.remove(id);
+    }
+    fn as_any(&self) -> &Any {
+        self
+    }
+    fn as_any_mut(&mut self) -> &mut Any {
+        self
+    }
+}
+
+// One ComponentStore per component type.
+#[derive(Default)]
+pub struct Components {
+    stores: HashMap<TypeId, Box<AnyStore>>
+}
+
+impl Components {
+    pub fn new() -> Self {
+        Components { stores: HashMap::new() }
+    }
+    // The store for components of type T, if any have been added.
+    pub fn store<T: 'static>(&self) -> Option<&ComponentStore<T>> {
+        self.stores.get(&TypeId::of::<T>())
+            .and_then(|store| store.as_any().downcast_ref())
+    }
+    // The store for components of type T, created if need be.
+    pub fn store_mut<T: 'static>(&mut self) -> &mut ComponentStore<T> {
+        self.stores.entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::new(ComponentStore::<T>::new()))
+            .as_any_mut()
+            .downcast_mut()
+            .unwrap()
+    }
+    // Removes every component the entity has.
+    pub fn remove_entity(&mut self, id: u64) {
+        for store in self.stores.values_mut() {
+            store.remove_entity(id);
+        }
+    }
+}
+
+#[test]
+fn test_components() {
+    let mut components = Components::new();
+    assert!(components.store::<i32>().is_none());
+    components.store_mut::<i32>().insert(1, 10);
+    components.store_mut::<&str>().insert(1, "goblin");
+    components.store_mut::<&str>().insert(2, "orc");
+    *components.store_mut::<i32>().get_mut(1).unwrap() -= 3;
+    assert_eq!(components.store::<i32>().unwrap().get(1), Some(&7));
+
+    components.remove_entity(1);
+    assert!(components.store::<i32>().unwrap().is_empty());
+    assert_eq!(components.store::<&str>().unwrap().get(1), None);
+    assert_eq!(components.store::<&str>().unwrap().get(2), Some(&"orc"));
+}
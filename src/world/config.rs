@@ -0,0 +1,221 @@
+extern crate rand;
+
+use prefab::Prefab;
+use world_map::Terrain;
+use self::rand::Rng;
+
+// Parameters controlling how WorldMap::generate lays out a dungeon.
+#[derive(Clone, Debug)]
+pub struct WorldMapConfig {
+    // Most rooms to place. As many as fit, up to this, are placed.
+    pub max_rooms: usize,
+    // Fewest rooms the map can end up with, up to max_rooms. Padding
+    // between rooms is relaxed if they don't fit, and generation fails with
+    // TooFewRooms if they still don't.
+    pub min_rooms: usize,
+    // Number of times to try placing a room before giving up.
+    pub room_attempts: u32,
+    // Inclusive bounds on a room's width and height (or a round room's
+    // radius), walls included.
+    pub min_room_size: i32,
+    pub max_room_size: i32,
+    // How sizes between those bounds are picked.
+    pub room_size_distribution: SizeDistribution,
+    // Minimum number of empty tiles between rooms.
+    pub room_padding: i32,
+    // Whether rooms may be built against each other.
+    pub wall_mode: WallMode,
+    // How many tiles deep generated rooms' walls are. Prefabs keep the walls
+    // they're drawn with.
+    pub wall_thickness: i32,
+    // Corridors dug between random rooms once every room is connected,
+    // adding loops to the layout.
+    pub corridor_count: u32,
+    // Shapes rooms are picked from, with their relative weights.
+    pub room_shapes: Vec<(RoomShape, u32)>,
+    // How corridors between rooms are laid out.
+    pub corridor_style: CorridorStyle,
+    // Where in each room corridors start and end.
+    pub corridor_target: CorridorTarget,
+    // How many tiles across corridors are.
+    pub corridor_width: i32,
+    // Terrains scattered over the floor once the map is laid out, with their
+    // relative weights; include Terrain::Floor to keep some plain floor.
+    // Empty leaves the floor alone.
+    pub floor_variety: Vec<(Terrain, u32)>,
+    // Hand-drawn rooms, and the chance from 0 to 1 that any one room is
+    // picked from them rather than generated.
+    pub prefabs: Vec<Prefab>,
+    pub prefab_chance: f64,
+    // Whether to clear away anything that can't be reached from the start.
+    pub prune_unreachable: bool
+}
+
+impl WorldMapConfig {
+    // Lots of small round chambers packed close, joined by winding tunnels
+    // that loop back on themselves, with rubble strewn about.
+    pub fn dense_cavern() -> Self {
+        WorldMapConfig {
+            max_rooms: 30,
+            min_room_size: 3,
+            max_room_size: 8,
+            corridor_count: 10,
+            room_shapes: vec![(RoomShape::Circular, 1), (RoomShape::Elliptical, 2)],
+            corridor_style: CorridorStyle::Drunken,
+            floor_variety: vec![(Terrain::Floor, 6), (Terrain::Rubble, 1)],
+            .. WorldMapConfig::default()
+        }
+    }
+    // A handful of big rectangular halls kept well apart, with long straight
+    // corridors between them and no loops.
+    pub fn sparse_dungeon() -> Self {
+        WorldMapConfig {
+            max_rooms: 6,
+            min_room_size: 8,
+            max_room_size: 16,
+            room_padding: 4,
+            room_shapes: vec![(RoomShape::Rectangular, 1)],
+            .. WorldMapConfig::default()
+        }
+    }
+    // Many cramped rooms built wall to wall, with plenty of extra doors and
+    // corridors, so there's more than one way everywhere.
+    pub fn tight_maze() -> Self {
+        WorldMapConfig {
+            max_rooms: 40,
+            min_room_size: 3,
+            max_room_size: 6,
+            wall_mode: WallMode::Shared,
+            corridor_count: 20,
+            room_shapes: vec![(RoomShape::Rectangular, 1)],
+            .. WorldMapConfig::default()
+        }
+    }
+    // The smallest room that can be placed: min_room_size across, or more if
+    // thick walls wouldn't leave any floor.
+    pub fn smallest_room_size(&self) -> i32 {
+        self.min_room_size.max(2 * self.wall_thickness + 1)
+    }
+    // Rooms smaller than 3 tiles across have no floor.
+    pub fn is_valid(&self) -> bool {
+        self.min_room_size >= 3 && self.min_room_size <= self.max_room_size &&
+            self.min_rooms <= self.max_rooms &&
+            self.room_padding >= 0 && self.corridor_width >= 1 &&
+            self.wall_thickness >= 1 && self.max_room_size > 2 * self.wall_thickness &&
+            (self.wall_mode == WallMode::Thick || self.room_padding == 0) &&
+            self.room_shapes.iter().any(|&(_, weight)| weight > 0) &&
+            self.floor_variety.iter().all(|&(terrain, _)| terrain.is_walkable()) &&
+            self.prefab_chance >= 0.0 && self.prefab_chance <= 1.0
+    }
+}
+
+impl Default for WorldMapConfig {
+    fn default() -> Self {
+        WorldMapConfig {
+            max_rooms: 12,
+            min_rooms: 0,
+            room_attempts: 1000,
+            min_room_size: 3,
+            max_room_size: 14,
+            room_size_distribution: SizeDistribution::Uniform,
+            room_padding: 0,
+            wall_mode: WallMode::Thick,
+            wall_thickness: 1,
+            corridor_count: 0,
+            room_shapes: vec![(RoomShape::Rectangular, 1), (RoomShape::Circular, 1)],
+            corridor_style: CorridorStyle::Straight,
+            corridor_target: CorridorTarget::Walls,
+            corridor_width: 1,
+            floor_variety: Vec::new(),
+            prefabs: Vec::new(),
+            prefab_chance: 0.0,
+            prune_unreachable: true
+        }
+    }
+}
+
+// Footprints a generated room can have.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum RoomShape {
+    Rectangular,
+    // Sized by radius rather than width and height.
+    Circular,
+    // The ellipse inscribed in a rectangle of the same size.
+    Elliptical
+}
+
+// How room sizes are spread between the smallest and largest allowed.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SizeDistribution {
+    // Every size is as likely as any other.
+    Uniform,
+    // The smaller of two uniform picks, so small rooms are common and the
+    // largest ones rare.
+    SmallBiased,
+    // Mostly rooms from the smaller half of the range, with one in five a
+    // hall from the top quarter.
+    Bimodal
+}
+
+impl SizeDistribution {
+    // A size from min to max, both inclusive.
+    pub fn sample<R: Rng>(&self, rng: &mut R, min: i32, max: i32) -> i32 {
+        let span = max - min;
+        match *self {
+            SizeDistribution::Uniform => rng.gen_range::<i32>(min, max + 1),
+            SizeDistribution::SmallBiased => {
+                let a = rng.gen_range::<i32>(min, max + 1);
+                let b = rng.gen_range::<i32>(min, max + 1);
+                a.min(b)
+            },
+            SizeDistribution::Bimodal => if rng.gen_range::<u32>(0, 5) == 0 {
+                rng.gen_range::<i32>(max - span / 4, max + 1)
+            } else {
+                rng.gen_range::<i32>(min, min + span / 2 + 1)
+            }
+        }
+    }
+}
+
+// How rooms' walls meet.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum WallMode {
+    // Every room keeps a wall of its own.
+    Thick,
+    // Neighbouring rooms can share a single wall, which gets a door if both
+    // rooms border it. Needs a room_padding of 0.
+    Shared
+}
+
+// Ways of digging a corridor between two rooms.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum CorridorStyle {
+    // The shortest path.
+    Straight,
+    // A winding random walk drifting toward the other room. Walks that
+    // wander too long fall back to the shortest path.
+    Drunken
+}
+
+// Which parts of two rooms a corridor joins.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum CorridorTarget {
+    // A random wall of each room, next to its floor.
+    Walls,
+    // The middle of each room. Doors go wherever the corridor crosses a
+    // wall, and the corridor always takes the shortest way.
+    Centers
+}
+
+// Reasons map generation can fail.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum GenError {
+    // The width or height isn't positive.
+    InvalidDimensions,
+    // Not even the smallest room fits in the map.
+    MapTooSmall,
+    // The config doesn't pass WorldMapConfig::is_valid.
+    InvalidConfig,
+    // Fewer rooms fit in the map than the level needs.
+    TooFewRooms
+}
@@ -0,0 +1,106 @@
+use config::{GenError, WorldMapConfig};
+use world_map::{seeded_rng, Location, WorldMap};
+
+// A stack of levels joined by stairs. Levels are generated the first time
+// they're entered, each from a seed derived from the dungeon's, so the same
+// seed always gives the same dungeon however it's explored.
+pub struct Dungeon {
+    seed: u64,
+    width: i32,
+    height: i32,
+    config: WorldMapConfig,
+    // Each level with its up and down stairs.
+    levels: Vec<(WorldMap, Location, Location)>,
+    current_level: usize
+}
+
+impl Dungeon {
+    // Creates a dungeon and generates its first level.
+    pub fn new(seed: u64, width: i32, height: i32, config: WorldMapConfig)
+               -> Result<Self, GenError> {
+        let mut dungeon = Dungeon {
+            seed: seed,
+            width: width,
+            height: height,
+            config: config,
+            levels: Vec::new(),
+            current_level: 0
+        };
+        let level = dungeon.generate_level(0)?;
+        dungeon.levels.push(level);
+
+        Ok(dungeon)
+    }
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+    // Index of the current level; the first level is 0.
+    pub fn current_level(&self) -> usize {
+        self.current_level
+    }
+    pub fn current(&self) -> &WorldMap {
+        &self.levels[self.current_level].0
+    }
+    pub fn current_mut(&mut self) -> &mut WorldMap {
+        &mut self.levels[self.current_level].0
+    }
+    // Stairs on the current level.
+    pub fn up_stairs(&self) -> Location {
+        self.levels[self.current_level].1
+    }
+    pub fn down_stairs(&self) -> Location {
+        self.levels[self.current_level].2
+    }
+    // Goes down a level, generating it if it's never been visited, and
+    // returns where the player arrives: the new level's up stairs.
+    pub fn descend(&mut self) -> Result<Location, GenError> {
+        let next = self.current_level + 1;
+        if next == self.levels.len() {
+            let level = self.generate_level(next)?;
+            self.levels.push(level);
+        }
+        self.current_level = next;
+
+        Ok(self.up_stairs())
+    }
+    // Goes up a level and returns where the player arrives: the down stairs
+    // of the level above. None if already on the first level.
+    pub fn ascend(&mut self) -> Option<Location> {
+        if self.current_level == 0 { return None; }
+        self.current_level -= 1;
+
+        Some(self.down_stairs())
+    }
+    fn generate_level(&self, index: usize) -> Result<(WorldMap, Location, Location), GenError> {
+        // Mix the index in so neighbouring levels get unrelated seeds.
+        let seed = self.seed ^ (index as u64).wrapping_mul(0x9e3779b97f4a7c15);
+        WorldMap::generate_level(&mut seeded_rng(seed), self.width, self.height, &self.config)
+    }
+}
+
+#[test]
+fn test_descend_and_ascend() {
+    let mut dungeon = Dungeon::new(42, 80, 50, WorldMapConfig::default()).unwrap();
+    let mut down_stairs = Vec::new();
+    for level in 1..4 {
+        down_stairs.push(dungeon.down_stairs());
+        let arrived = dungeon.descend().unwrap();
+        assert_eq!(dungeon.current_level(), level);
+        assert_eq!(arrived, dungeon.up_stairs());
+    }
+    for level in (0..3).rev() {
+        let arrived = dungeon.ascend().unwrap();
+        assert_eq!(dungeon.current_level(), level);
+        assert_eq!(arrived, down_stairs[level]);
+    }
+    assert!(dungeon.ascend().is_none());
+
+    // Going back down finds the level as it was left, and a second dungeon
+    // from the same seed is laid out the same way.
+    let ascii = dungeon.current().to_ascii();
+    let mut again = Dungeon::new(42, 80, 50, WorldMapConfig::default()).unwrap();
+    assert_eq!(again.current().to_ascii(), ascii);
+    dungeon.descend().unwrap();
+    again.descend().unwrap();
+    assert_eq!(again.current().to_ascii(), dungeon.current().to_ascii());
+}
@@ -0,0 +1,284 @@
+use std::collections::HashSet;
+use world_map::{Location, Visibility, WorldMap};
+
+// Rules for deciding what's in view.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum FovMode {
+    // Plain shadowcasting. Near walls and corners, a tile can be in view
+    // without the origin being in view from it.
+    Standard,
+    // Shadowcasting, but a transparent tile only counts as visible if the
+    // origin would be in view from it as well, so two open tiles either
+    // see each other or don't. Opaque tiles can't look back, so walls are
+    // visible under the standard rule.
+    Symmetric
+}
+
+// Transforms from octant-relative coordinates to map coordinates, one
+// column per octant.
+const OCTANTS: [[i32; 8]; 4] = [
+    [1, 0, 0, -1, -1, 0, 0, 1],
+    [0, 1, -1, 0, 0, -1, 1, 0],
+    [0, 1, 1, 0, 0, -1, -1, 0],
+    [1, 0, 0, 1, -1, 0, 0, -1]
+];
+
+impl WorldMap {
+    // Tiles visible from the origin within the given (Euclidean) radius,
+    // found by recursive shadowcasting. Opaque tiles are visible themselves
+    // but hide what's behind them; the origin is always visible.
+    pub fn field_of_view(&self, origin: Location, radius: i32) -> HashSet<Location> {
+        self.field_of_view_with_mode(origin, radius, FovMode::Standard)
+    }
+    pub fn field_of_view_with_mode(&self, origin: Location, radius: i32,
+                                   mode: FovMode) -> HashSet<Location> {
+        let visible = self.shadowcast(origin, radius);
+        match mode {
+            FovMode::Standard => visible,
+            FovMode::Symmetric => visible.iter()
+                .cloned()
+                .filter(|&loc| loc == origin ||
+                        !self.get_tile(loc).terrain.is_transparent() ||
+                        self.shadowcast(loc, radius).contains(&origin))
+                .collect()
+        }
+    }
+    fn shadowcast(&self, origin: Location, radius: i32) -> HashSet<Location> {
+        let mut visible = HashSet::new();
+        visible.insert(origin);
+        for octant in 0..8 {
+            self.cast_light(&mut visible, origin, radius, 1, 1.0, 0.0,
+                            OCTANTS[0][octant], OCTANTS[1][octant],
+                            OCTANTS[2][octant], OCTANTS[3][octant]);
+        }
+
+        visible
+    }
+    // Marks the tiles in view from the origin as visible, and the ones
+    // that were visible but no longer are as remembered.
+    pub fn update_visibility(&mut self, origin: Location, radius: i32) {
+        // Only touch tiles whose visibility changes, so dirty tracking
+        // doesn't see the whole map every turn.
+        let visible = self.field_of_view(origin, radius);
+        for y in 0..self.height() {
+            for x in 0..self.width() {
+                let loc = Location::new(x, y);
+                let was_visible = self.get_tile(loc).visibility() == Visibility::Visible;
+                if was_visible && !visible.contains(&loc) {
+                    self.get_tile_mut(loc).set_visibility(Visibility::Remembered);
+                }
+            }
+        }
+        for loc in visible {
+            if self.get_tile(loc).visibility() != Visibility::Visible {
+                self.get_tile_mut(loc).set_visibility(Visibility::Visible);
+            }
+        }
+    }
+    // Whether nothing opaque lies on the Bresenham line between two
+    // locations. The endpoints themselves never block.
+    pub fn has_line_of_sight(&self, from: Location, to: Location) -> bool {
+        let dx = (to.x - from.x).abs();
+        let dy = -(to.y - from.y).abs();
+        let step_x = if from.x < to.x { 1 } else { -1 };
+        let step_y = if from.y < to.y { 1 } else { -1 };
+        let mut error = dx + dy;
+        let mut loc = from;
+        while loc != to {
+            let doubled = 2 * error;
+            if doubled >= dy {
+                error += dy;
+                loc.x += step_x;
+            }
+            if doubled <= dx {
+                error += dx;
+                loc.y += step_y;
+            }
+            if loc == to { break; }
+
+            match self.try_get_tile(loc) {
+                Some(tile) => if !tile.terrain.is_transparent() { return false; },
+                None => return false
+            }
+        }
+
+        true
+    }
+    // Scans one octant row by row, starting at `row`, between the given
+    // start and end slopes. Recurses whenever an opaque tile splits the
+    // visible range.
+    fn cast_light(&self, visible: &mut HashSet<Location>, origin: Location, radius: i32,
+                  row: i32, mut start: f64, end: f64, xx: i32, xy: i32, yx: i32, yy: i32) {
+        if start < end { return; }
+
+        let mut new_start = 0.0;
+        for j in row..radius + 1 {
+            let dy = -j;
+            let mut blocked = false;
+            for dx in -j..1 {
+                let l_slope = (dx as f64 - 0.5) / (dy as f64 + 0.5);
+                let r_slope = (dx as f64 + 0.5) / (dy as f64 - 0.5);
+                if start < r_slope {
+                    continue;
+                } else if end > l_slope {
+                    break;
+                }
+
+                let loc = Location::new(origin.x + dx * xx + dy * xy,
+                                        origin.y + dx * yx + dy * yy);
+                let opaque = match self.try_get_tile(loc) {
+                    Some(tile) => {
+                        if dx * dx + dy * dy <= radius * radius {
+                            visible.insert(loc);
+                        }
+                        !tile.terrain.is_transparent()
+                    },
+                    None => true
+                };
+                if blocked {
+                    if opaque {
+                        new_start = r_slope;
+                    } else {
+                        blocked = false;
+                        start = new_start;
+                    }
+                } else if opaque && j < radius {
+                    blocked = true;
+                    self.cast_light(visible, origin, radius, j + 1, start, l_slope,
+                                    xx, xy, yx, yy);
+                    new_start = r_slope;
+                }
+            }
+            if blocked { break; }
+        }
+    }
+}
+
+#[cfg(test)]
+fn open_floor(width: i32, height: i32) -> WorldMap {
+    use world_map::Terrain;
+
+    let mut world = WorldMap::new(width, height);
+    for x in 0..width {
+        for y in 0..height {
+            world.get_tile_mut(Location::new(x, y)).terrain = Terrain::Floor;
+        }
+    }
+
+    world
+}
+
+#[test]
+fn test_field_of_view_shadow() {
+    use world_map::Terrain;
+
+    let mut world = open_floor(9, 9);
+    world.get_tile_mut(Location::new(3, 4)).terrain = Terrain::Wall;
+    let visible = world.field_of_view(Location::new(1, 4), 10);
+
+    assert!(visible.contains(&Location::new(1, 4)));
+    assert!(visible.contains(&Location::new(3, 4)));
+    assert!(visible.contains(&Location::new(3, 3)));
+    assert!(visible.contains(&Location::new(8, 0)));
+    assert!(visible.contains(&Location::new(8, 8)));
+
+    // Directly behind the wall is in shadow.
+    assert!(!visible.contains(&Location::new(4, 4)));
+    assert!(!visible.contains(&Location::new(6, 4)));
+    assert!(!visible.contains(&Location::new(8, 4)));
+}
+
+#[test]
+fn test_field_of_view_radius() {
+    let world = open_floor(9, 9);
+    let origin = Location::new(4, 4);
+    let visible = world.field_of_view(origin, 3);
+
+    assert!(visible.contains(&Location::new(7, 4)));
+    assert!(visible.contains(&Location::new(6, 6)));
+    assert!(!visible.contains(&Location::new(7, 6)));
+    assert!(!visible.contains(&Location::new(8, 4)));
+    assert!(visible.iter().all(|loc| loc.euclidean_squared(&origin) <= 9));
+}
+
+#[test]
+fn test_line_of_sight() {
+    use world_map::Terrain;
+
+    let mut world = open_floor(7, 5);
+    world.get_tile_mut(Location::new(3, 2)).terrain = Terrain::Wall;
+
+    assert!(!world.has_line_of_sight(Location::new(0, 2), Location::new(6, 2)));
+    assert!(!world.has_line_of_sight(Location::new(6, 2), Location::new(0, 2)));
+    assert!(world.has_line_of_sight(Location::new(0, 0), Location::new(6, 0)));
+    assert!(world.has_line_of_sight(Location::new(0, 4), Location::new(6, 4)));
+
+    // Endpoints don't block, so the wall itself can be seen.
+    assert!(world.has_line_of_sight(Location::new(0, 2), Location::new(3, 2)));
+    assert!(world.has_line_of_sight(Location::new(3, 2), Location::new(6, 2)));
+    assert!(world.has_line_of_sight(Location::new(1, 1), Location::new(1, 1)));
+}
+
+#[test]
+fn test_update_visibility() {
+    let mut world = open_floor(9, 1);
+    let (near, far) = (Location::new(2, 0), Location::new(8, 0));
+    assert_eq!(world.get_tile(near).visibility(), Visibility::Unseen);
+
+    world.update_visibility(Location::new(0, 0), 3);
+    assert_eq!(world.get_tile(near).visibility(), Visibility::Visible);
+    assert_eq!(world.get_tile(far).visibility(), Visibility::Unseen);
+
+    world.update_visibility(Location::new(7, 0), 3);
+    assert_eq!(world.get_tile(near).visibility(), Visibility::Remembered);
+    assert_eq!(world.get_tile(far).visibility(), Visibility::Visible);
+}
+
+#[test]
+fn test_symmetric_field_of_view() {
+    use world_map::Terrain;
+
+    // The end of a wall along the top row:
+    // ....#..
+    // .@.....
+    let mut world = open_floor(7, 7);
+    world.get_tile_mut(Location::new(4, 0)).terrain = Terrain::Wall;
+    let (origin, corner) = (Location::new(1, 1), Location::new(5, 0));
+
+    // Light just grazes past the wall end one way but not the other.
+    assert!(world.field_of_view(origin, 8).contains(&corner));
+    assert!(!world.field_of_view(corner, 8).contains(&origin));
+
+    let visible = world.field_of_view_with_mode(origin, 8, FovMode::Symmetric);
+    assert!(!visible.contains(&corner));
+    assert!(visible.contains(&Location::new(4, 0)));
+    for loc in visible.iter().filter(|loc| world.get_tile(**loc).terrain == Terrain::Floor) {
+        let back = world.field_of_view_with_mode(*loc, 8, FovMode::Symmetric);
+        assert!(back.contains(&origin), "{:?} can't see back", loc);
+    }
+}
+
+#[test]
+fn test_chasm_and_fog() {
+    use world_map::Terrain;
+
+    let world = WorldMap::from_ascii("#######\n\
+                                      #.._..#\n\
+                                      #######\n\
+                                      #..%..#\n\
+                                      #######").unwrap();
+
+    // A chasm can be seen across but not crossed.
+    let (near, far) = (Location::new(1, 1), Location::new(5, 1));
+    assert!(world.field_of_view(near, 8).contains(&far));
+    assert!(world.has_line_of_sight(near, far));
+    assert!(world.find_path(near, far).is_none());
+    assert!(!Terrain::Chasm.is_walkable() && Terrain::Chasm.is_transparent());
+
+    // Fog is the other way around.
+    let (near, far) = (Location::new(1, 3), Location::new(5, 3));
+    assert!(!world.field_of_view(near, 8).contains(&far));
+    assert!(!world.has_line_of_sight(near, far));
+    assert_eq!(world.find_path(near, far).map(|path| path.len()), Some(5));
+    assert!(Terrain::Fog.is_walkable() && !Terrain::Fog.is_transparent());
+}
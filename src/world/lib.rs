@@ -1,4 +1,26 @@
 mod world_map;
 mod random;
+mod config;
+mod bsp;
+mod fov;
+mod dungeon;
+mod rect;
+mod component;
+mod stats;
+mod prefab;
+mod overworld;
+mod scheduler;
+#[cfg(any(test, feature = "test-util"))]
+mod scripted_rng;
 pub use world_map::*;
 pub use random::*;
+pub use config::*;
+pub use dungeon::*;
+pub use rect::*;
+pub use component::*;
+pub use stats::*;
+pub use prefab::*;
+pub use fov::*;
+pub use scheduler::*;
+#[cfg(any(test, feature = "test-util"))]
+pub use scripted_rng::*;
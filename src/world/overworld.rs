@@ -0,0 +1,114 @@
+extern crate rand;
+
+use world_map::{Terrain, WorldMap};
+use self::rand::Rng;
+
+// Layers of noise summed into the heightmap, each twice as fine and half as
+// strong as the one before.
+const OCTAVES: u32 = 3;
+
+impl WorldMap {
+    // Generates open country from value noise: random heights on a grid
+    // `scale` tiles apart are smoothly blended across the map, with finer
+    // grids layered on top for detail. Low ground is water, middling ground
+    // is floor and grass, and high ground is impassable mountain wall. The
+    // same rng state always gives the same map.
+    pub fn generate_overworld<R: Rng>(rng: &mut R, width: i32, height: i32, scale: f64) -> Self {
+        assert!(scale > 0.0, "Noise scale must be positive.");
+
+        let mut world = WorldMap::new(width, height);
+        let mut heights = vec![0.0; width as usize * height as usize];
+        let (mut amplitude, mut total, mut octave_scale) = (1.0, 0.0, scale);
+        for _ in 0..OCTAVES {
+            let noise = ValueNoise::new(rng, width, height, octave_scale);
+            for (index, height) in heights.iter_mut().enumerate() {
+                let loc = world.location_of(index);
+                *height += amplitude * noise.at(loc.x as f64, loc.y as f64);
+            }
+            total += amplitude;
+            amplitude /= 2.0;
+            octave_scale = (octave_scale / 2.0).max(1.0);
+        }
+        for (index, height) in heights.into_iter().enumerate() {
+            let loc = world.location_of(index);
+            world.get_tile_mut(loc).terrain = terrain_for_height(height / total);
+        }
+
+        world
+    }
+}
+
+// Heights run from 0 to 1.
+fn terrain_for_height(height: f64) -> Terrain {
+    if height < 0.35 {
+        Terrain::Water
+    } else if height < 0.45 {
+        Terrain::Floor
+    } else if height < 0.65 {
+        Terrain::Grass
+    } else {
+        Terrain::Wall
+    }
+}
+
+// Random values from 0 to 1 on a lattice covering the map, read back with
+// smoothed bilinear interpolation.
+struct ValueNoise {
+    lattice: Vec<f64>,
+    columns: usize,
+    scale: f64
+}
+
+impl ValueNoise {
+    fn new<R: Rng>(rng: &mut R, width: i32, height: i32, scale: f64) -> Self {
+        // One extra point past the far edge to interpolate toward.
+        let columns = (width as f64 / scale).ceil() as usize + 2;
+        let rows = (height as f64 / scale).ceil() as usize + 2;
+        let lattice = (0..columns * rows).map(|_| rng.gen::<f64>()).collect();
+
+        ValueNoise {lattice: lattice, columns: columns, scale: scale}
+    }
+    fn at(&self, x: f64, y: f64) -> f64 {
+        let (x, y) = (x / self.scale, y / self.scale);
+        let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+        let (tx, ty) = (smoothstep(x - x0 as f64), smoothstep(y - y0 as f64));
+        let point = |x: usize, y: usize| self.lattice[y * self.columns + x];
+
+        let top = lerp(point(x0, y0), point(x0 + 1, y0), tx);
+        let bottom = lerp(point(x0, y0 + 1), point(x0 + 1, y0 + 1), tx);
+        lerp(top, bottom, ty)
+    }
+}
+
+fn lerp(a: f64, b: f64, t: f64) -> f64 {
+    a + (b - a) * t
+}
+
+// Eases in and out so the lattice doesn't show through as creases.
+fn smoothstep(t: f64) -> f64 {
+    t * t * (3.0 - 2.0 * t)
+}
+
+#[test]
+fn test_generate_overworld() {
+    use world_map::seeded_rng;
+
+    let world = WorldMap::generate_overworld(&mut seeded_rng(9), 80, 50, 12.0);
+    let again = WorldMap::generate_overworld(&mut seeded_rng(9), 80, 50, 12.0);
+    assert_eq!(world.to_ascii(), again.to_ascii());
+    for &terrain in [Terrain::Water, Terrain::Grass, Terrain::Wall].iter() {
+        assert!(world.tiles().any(|(tile, _)| tile.terrain == terrain), "No {:?}", terrain);
+    }
+    assert!(!world.tiles().any(|(tile, _)| tile.terrain == Terrain::Nothing));
+
+    let other = WorldMap::generate_overworld(&mut seeded_rng(10), 80, 50, 12.0);
+    assert!(world.to_ascii() != other.to_ascii());
+}
+
+#[test]
+fn test_terrain_for_height() {
+    assert_eq!(terrain_for_height(0.0), Terrain::Water);
+    assert_eq!(terrain_for_height(0.4), Terrain::Floor);
+    assert_eq!(terrain_for_height(0.5), Terrain::Grass);
+    assert_eq!(terrain_for_height(1.0), Terrain::Wall);
+}
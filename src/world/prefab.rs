@@ -0,0 +1,55 @@
+use world_map::{FeatureBuilder, Location, Terrain};
+
+// A hand-drawn room, such as a vault or a shrine, that the generator can
+// place instead of a random one.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Prefab {
+    components: Vec<(Location, Terrain)>
+}
+
+impl Prefab {
+    // Reads a prefab written with the same symbols as WorldMap::to_ascii.
+    // Short rows are padded out with empty space. Panics if a symbol isn't
+    // a terrain or there's no floor, since prefabs are written by hand.
+    pub fn from_ascii(ascii: &str) -> Prefab {
+        let mut components = Vec::new();
+        for (y, line) in ascii.lines().enumerate() {
+            for (x, c) in line.chars().enumerate() {
+                let terrain = match Terrain::from_char(c) {
+                    Some(terrain) => terrain,
+                    None => panic!("Prefab symbol {:?} isn't a terrain.", c)
+                };
+                components.push((Location::new(x as i32, y as i32), terrain));
+            }
+        }
+        assert!(components.iter().any(|c| c.1 == Terrain::Floor), "Prefabs need some floor.");
+
+        Prefab { components: components }
+    }
+    pub fn width(&self) -> i32 {
+        self.components.iter().map(|c| c.0.x).max().unwrap() + 1
+    }
+    pub fn height(&self) -> i32 {
+        self.components.iter().map(|c| c.0.y).max().unwrap() + 1
+    }
+    // A builder for placing the prefab, top-left corner first.
+    pub fn builder(&self) -> FeatureBuilder {
+        FeatureBuilder::new(self.components.clone())
+    }
+}
+
+#[test]
+fn test_prefab_from_ascii() {
+    let prefab = Prefab::from_ascii("#####\n#...#\n#.#.#\n#...\n####");
+    assert_eq!(prefab.width(), 5);
+    assert_eq!(prefab.height(), 5);
+    let feature = prefab.builder().location(Location::new(10, 20)).build();
+    assert_eq!(feature.floors().count(), 8);
+    assert!(feature.iter().any(|&c| c == (Location::new(12, 22), Terrain::Wall)));
+}
+
+#[test]
+#[should_panic]
+fn test_prefab_rejects_unknown_symbols() {
+    Prefab::from_ascii("#x#");
+}
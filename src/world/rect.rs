@@ -0,0 +1,153 @@
+#[cfg(feature = "serde")]
+extern crate serde;
+
+use world_map::Location;
+#[cfg(feature = "serde")]
+use self::serde::{Deserialize, Serialize};
+
+// A rectangle of tiles, such as a room's bounding box or a viewport. x and y
+// are the top-left corner.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32
+}
+
+impl Rect {
+    pub fn new(x: i32, y: i32, width: i32, height: i32) -> Self {
+        Rect { x: x, y: y, width: width, height: height }
+    }
+    // Smallest rectangle holding every location; empty if there are none.
+    pub fn bounding<'a, I>(locations: I) -> Self where I: IntoIterator<Item=&'a Location> {
+        let mut locations = locations.into_iter();
+        let first = match locations.next() {
+            Some(loc) => *loc,
+            None => return Rect::new(0, 0, 0, 0)
+        };
+        let (mut min, mut max) = (first, first);
+        for loc in locations {
+            min = Location::new(min.x.min(loc.x), min.y.min(loc.y));
+            max = Location::new(max.x.max(loc.x), max.y.max(loc.y));
+        }
+
+        Rect::new(min.x, min.y, max.x - min.x + 1, max.y - min.y + 1)
+    }
+    // Whether the rectangle holds no tiles at all.
+    pub fn is_empty(&self) -> bool {
+        self.width <= 0 || self.height <= 0
+    }
+    pub fn contains(&self, loc: Location) -> bool {
+        loc.x >= self.x && loc.y >= self.y &&
+            loc.x < self.x + self.width && loc.y < self.y + self.height
+    }
+    // Whether the rectangles share at least one tile; rectangles that only
+    // touch along an edge don't.
+    pub fn intersects(&self, other: &Rect) -> bool {
+        !self.is_empty() && !other.is_empty() &&
+            self.x < other.x + other.width && other.x < self.x + self.width &&
+            self.y < other.y + other.height && other.y < self.y + self.height
+    }
+    // How many tiles of space there are between the rectangles, going
+    // straight across or diagonally: 0 if they touch, and less than 0 if
+    // they overlap, by as many tiles as they overlap in the narrower
+    // direction.
+    pub fn gap(&self, other: &Rect) -> i32 {
+        let gap_x = (other.x - (self.x + self.width)).max(self.x - (other.x + other.width));
+        let gap_y = (other.y - (self.y + self.height)).max(self.y - (other.y + other.height));
+        gap_x.max(gap_y)
+    }
+    // The middle tile, rounding up and to the left.
+    pub fn center(&self) -> Location {
+        Location::new((2 * self.x + self.width - 1) / 2, (2 * self.y + self.height - 1) / 2)
+    }
+    // The tiles around the edge of the rectangle, which for a room are its
+    // walls, clockwise from the top-left corner.
+    pub fn perimeter(&self) -> Vec<Location> {
+        if self.is_empty() { return Vec::new(); }
+        let (right, bottom) = (self.x + self.width - 1, self.y + self.height - 1);
+        let mut perimeter: Vec<_> = (self.x..right + 1).map(|x| Location::new(x, self.y)).collect();
+        perimeter.extend((self.y + 1..bottom + 1).map(|y| Location::new(right, y)));
+        if bottom > self.y {
+            perimeter.extend((self.x..right).rev().map(|x| Location::new(x, bottom)));
+        }
+        if right > self.x {
+            perimeter.extend((self.y + 1..bottom).rev().map(|y| Location::new(self.x, y)));
+        }
+
+        perimeter
+    }
+    // Grows the rectangle by the given number of tiles on every side.
+    pub fn inflate(&self, amount: i32) -> Self {
+        Rect::new(self.x - amount, self.y - amount,
+                  self.width + 2 * amount, self.height + 2 * amount)
+    }
+    // The part of the rectangle inside a map of the given size. Rectangles
+    // entirely outside the map come out empty.
+    pub fn clamp_to(&self, width: i32, height: i32) -> Self {
+        let (min_x, min_y) = (self.x.max(0), self.y.max(0));
+        let max_x = self.x.saturating_add(self.width).min(width);
+        let max_y = self.y.saturating_add(self.height).min(height);
+
+        Rect::new(min_x, min_y, (max_x - min_x).max(0), (max_y - min_y).max(0))
+    }
+}
+
+#[test]
+fn test_rect_contains() {
+    let rect = Rect::new(2, 3, 4, 2);
+    assert!(rect.contains(Location::new(2, 3)));
+    assert!(rect.contains(Location::new(5, 4)));
+    assert!(!rect.contains(Location::new(6, 4)));
+    assert!(!rect.contains(Location::new(5, 5)));
+    assert!(!rect.contains(Location::new(1, 3)));
+    assert!(!Rect::new(0, 0, 0, 0).contains(Location::new(0, 0)));
+    assert_eq!(rect.center(), Location::new(3, 3));
+}
+
+#[test]
+fn test_rect_intersects() {
+    let rect = Rect::new(0, 0, 3, 3);
+    assert!(rect.intersects(&rect));
+    assert!(rect.intersects(&Rect::new(2, 2, 3, 3)));
+    assert!(rect.intersects(&Rect::new(1, 1, 1, 1)));
+    // Touching edges and corners don't count.
+    assert!(!rect.intersects(&Rect::new(3, 0, 3, 3)));
+    assert!(!rect.intersects(&Rect::new(3, 3, 1, 1)));
+    assert!(!rect.intersects(&Rect::new(1, 1, 0, 5)));
+    assert!(rect.inflate(1).intersects(&Rect::new(3, 3, 1, 1)));
+}
+
+#[test]
+fn test_rect_clamp_to() {
+    assert_eq!(Rect::new(-2, 1, 5, 10).clamp_to(4, 4), Rect::new(0, 1, 3, 3));
+    assert!(Rect::new(5, 5, 2, 2).clamp_to(4, 4).is_empty());
+    assert_eq!(Rect::bounding(&[Location::new(3, 1), Location::new(1, 4)]),
+               Rect::new(1, 1, 3, 4));
+}
+
+#[test]
+fn test_rect_perimeter() {
+    let l = |x, y| Location::new(x, y);
+    assert_eq!(Rect::new(1, 1, 4, 3).perimeter(), vec![
+        l(1, 1), l(2, 1), l(3, 1), l(4, 1),
+        l(4, 2),
+        l(4, 3), l(3, 3), l(2, 3), l(1, 3),
+        l(1, 2)]);
+    assert_eq!(Rect::new(0, 0, 3, 1).perimeter(), vec![l(0, 0), l(1, 0), l(2, 0)]);
+    assert_eq!(Rect::new(0, 0, 1, 2).perimeter(), vec![l(0, 0), l(0, 1)]);
+    assert!(Rect::new(0, 0, 0, 2).perimeter().is_empty());
+}
+
+#[test]
+fn test_rect_gap() {
+    let rect = Rect::new(0, 0, 3, 3);
+    assert_eq!(rect.gap(&Rect::new(3, 0, 2, 2)), 0);
+    assert_eq!(rect.gap(&Rect::new(5, 1, 2, 2)), 2);
+    assert_eq!(Rect::new(5, 1, 2, 2).gap(&rect), 2);
+    assert_eq!(rect.gap(&Rect::new(4, 6, 1, 1)), 3);
+    assert_eq!(rect.gap(&Rect::new(2, 1, 3, 3)), -1);
+    assert_eq!(rect.gap(&rect), -3);
+}
@@ -0,0 +1,108 @@
+// Energy an entity has to save up to take a turn.
+const TURN_COST: u32 = 100;
+
+// Decides whose turn it is, by entity id. Every tick each entity gains its
+// speed in energy, and whoever has saved up enough acts next and pays for
+// it, so a speed 2 entity acts twice as often as a speed 1 one.
+#[derive(Clone, Debug, Default)]
+pub struct Scheduler {
+    // Id, speed and energy, in the order entities were added.
+    actors: Vec<(u64, u32, u32)>
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Scheduler { actors: Vec::new() }
+    }
+    // Adds an entity with no energy saved, or changes its speed if it's
+    // already scheduled.
+    pub fn add_entity(&mut self, id: u64, speed: u32) {
+        assert!(speed > 0, "Entities need some speed to ever get a turn.");
+        if let Some(actor) = self.actors.iter_mut().find(|actor| actor.0 == id) {
+            actor.1 = speed;
+            return;
+        }
+        self.actors.push((id, speed, 0));
+    }
+    // Returns false if the entity wasn't scheduled.
+    pub fn remove_entity(&mut self, id: u64) -> bool {
+        let before = self.actors.len();
+        self.actors.retain(|actor| actor.0 != id);
+        self.actors.len() != before
+    }
+    pub fn len(&self) -> usize {
+        self.actors.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.actors.is_empty()
+    }
+    // The entity to act next: whoever has the most energy once someone can
+    // afford a turn, the earliest added winning ties. Panics if there's
+    // nobody to schedule.
+    pub fn next(&mut self) -> u64 {
+        assert!(self.actors.len() > 0, "Nobody to schedule.");
+
+        // Skip ahead to the first tick anyone can afford a turn on. Energy
+        // saturates rather than overflowing for absurdly fast entities.
+        let ticks = self.actors.iter()
+            .map(|&(_, speed, energy)| {
+                let needed = TURN_COST.saturating_sub(energy);
+                needed / speed + if needed % speed > 0 { 1 } else { 0 }
+            })
+            .min()
+            .unwrap();
+        for actor in self.actors.iter_mut() {
+            actor.2 = actor.2.saturating_add(ticks.saturating_mul(actor.1));
+        }
+
+        let mut next = 0;
+        for (i, actor) in self.actors.iter().enumerate() {
+            if actor.2 > self.actors[next].2 { next = i; }
+        }
+        self.actors[next].2 -= TURN_COST;
+        self.actors[next].0
+    }
+}
+
+#[test]
+fn test_scheduler_speeds() {
+    let mut scheduler = Scheduler::new();
+    scheduler.add_entity(7, 1);
+    scheduler.add_entity(3, 2);
+    let turns: Vec<_> = (0..3000).map(|_| scheduler.next()).collect();
+    let fast = turns.iter().filter(|&&id| id == 3).count();
+    let slow = turns.iter().filter(|&&id| id == 7).count();
+    assert_eq!(fast + slow, 3000);
+    assert_eq!(fast, 2 * slow);
+
+    // Nobody goes long without a turn.
+    assert!(turns.windows(3).all(|window| window.contains(&7)));
+}
+
+#[test]
+fn test_scheduler_add_and_remove() {
+    let mut scheduler = Scheduler::new();
+    scheduler.add_entity(1, 5);
+    scheduler.add_entity(2, 5);
+    assert_eq!((scheduler.next(), scheduler.next(), scheduler.next()), (1, 2, 1));
+    assert!(scheduler.remove_entity(1));
+    assert!(!scheduler.remove_entity(1));
+    assert_eq!(scheduler.len(), 1);
+    assert_eq!((scheduler.next(), scheduler.next()), (2, 2));
+
+    // Changing a speed keeps the entity's place.
+    scheduler.add_entity(2, 1);
+    scheduler.add_entity(4, 300);
+    assert_eq!(scheduler.len(), 2);
+    assert_eq!((scheduler.next(), scheduler.next()), (4, 4));
+}
+
+#[test]
+fn test_scheduler_huge_speeds() {
+    let mut scheduler = Scheduler::new();
+    scheduler.add_entity(1, u32::max_value());
+    scheduler.add_entity(2, u32::max_value() - 1);
+    scheduler.add_entity(3, 1);
+    let turns: Vec<_> = (0..1000).map(|_| scheduler.next()).collect();
+    assert!(turns.contains(&1) && turns.contains(&2));
+}
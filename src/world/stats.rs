@@ -0,0 +1,63 @@
+extern crate rand;
+
+use config::{GenError, WorldMapConfig};
+use world_map::{Location, Terrain, WorldMap};
+use self::rand::Rng;
+
+// Summary of what a map contains, for tuning generation settings.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct GenStats {
+    pub room_count: usize,
+    // Tiles that can be walked on, doors and stairs included.
+    pub floor_tiles: usize,
+    pub wall_tiles: usize,
+    // Number of separate 4-connected walkable regions.
+    pub connected_components: usize,
+    // Tiles in the largest of those regions.
+    pub largest_region_size: usize
+}
+
+impl WorldMap {
+    // Like generate_with_config, but also reports what was generated.
+    pub fn generate_with_stats<R: Rng>(rng: &mut R, width: i32, height: i32,
+                                       config: &WorldMapConfig)
+                                       -> Result<(Self, Location, GenStats), GenError> {
+        let (world, start) = WorldMap::try_generate_with_config(rng, width, height, config)?;
+        let stats = world.stats();
+
+        Ok((world, start, stats))
+    }
+    // Counts up what's in the map as it is now.
+    pub fn stats(&self) -> GenStats {
+        let regions = self.connected_regions();
+        GenStats {
+            room_count: self.rooms().len(),
+            floor_tiles: self.tiles().filter(|&(tile, _)| tile.terrain.is_walkable()).count(),
+            wall_tiles: self.tiles().filter(|&(tile, _)| tile.terrain == Terrain::Wall).count(),
+            connected_components: regions.len(),
+            largest_region_size: regions.first().map_or(0, |region| region.len())
+        }
+    }
+}
+
+#[test]
+fn test_generate_with_stats() {
+    use world_map::seeded_rng;
+
+    let mut rng = seeded_rng(8);
+    let (world, _, stats) = WorldMap::generate_with_stats(&mut rng, 80, 50,
+                                                          &WorldMapConfig::default()).unwrap();
+    let mut floor_tiles = 0;
+    for y in 0..world.height() {
+        for x in 0..world.width() {
+            if world.get_tile(Location::new(x, y)).terrain.is_walkable() {
+                floor_tiles += 1;
+            }
+        }
+    }
+    assert_eq!(stats.floor_tiles, floor_tiles);
+    assert_eq!(stats.room_count, world.rooms().len());
+    assert_eq!(stats.connected_components, 1);
+    assert_eq!(stats.largest_region_size, floor_tiles);
+    assert!(stats.wall_tiles > 0);
+}
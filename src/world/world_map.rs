@@ -1,560 +1,2613 @@
 extern crate astar;
 extern crate rand;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
 
+use component::{ComponentStore, Components};
+use config::{CorridorStyle, CorridorTarget, GenError, RoomShape, WallMode, WorldMapConfig};
 use random::RandomTable;
-use self::rand::{Rng};
+use rect::Rect;
+use self::rand::{Rng, SeedableRng, StdRng};
+#[cfg(feature = "serde")]
+use self::serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+use std::ops::Range;
 
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct WorldMap {
     width: i32,
     height: i32,
-    tiles: Vec<Tile>
+    tiles: Vec<Tile>,
+    // Id given to the next spawned entity; ids are never reused.
+    next_entity_id: u64,
+    // Where each entity is, kept up to date by spawn_entity, move_entity and
+    // remove_entity.
+    entity_locations: HashMap<u64, Location>,
+    // Bounding boxes of the rooms the map was generated with.
+    rooms: Vec<Rect>,
+    // Locations given each tag by tag_region.
+    #[cfg_attr(feature = "serde", serde(default))]
+    tags: HashMap<String, HashSet<Location>>,
+    // Gameplay data attached to entities.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    components: Components,
+    // Tiles borrowed mutably since take_dirty last ran; None while tracking
+    // is off.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    dirty: Option<HashSet<Location>>
 }
 
 impl WorldMap {
-    pub fn generate<R: Rng>(rng: &mut R, width: i32, height: i32) -> (Self, Location) {
+    // Creates an empty map filled with Terrain::Nothing.
+    pub fn new(width: i32, height: i32) -> Self {
         assert!(width > 0);
         assert!(height > 0);
 
         let tiles: Vec<_> = ::std::iter::repeat(Terrain::Nothing)
-            .take((width * height) as usize)
+            .take(width as usize * height as usize)
             .map(|terrain| Tile::new(terrain))
             .collect();
 
-        let mut world = WorldMap { width: width, height: height, tiles: tiles };
+        WorldMap {
+            width: width,
+            height: height,
+            tiles: tiles,
+            next_entity_id: 0,
+            entity_locations: HashMap::new(),
+            rooms: Vec::new(),
+            tags: HashMap::new(),
+            components: Components::new(),
+            dirty: None
+        }
+    }
+    pub fn generate<R: Rng>(rng: &mut R, width: i32, height: i32) -> (Self, Location) {
+        Self::try_generate(rng, width, height).unwrap()
+    }
+    // Like generate, but reports bad dimensions instead of panicking.
+    pub fn try_generate<R: Rng>(rng: &mut R, width: i32, height: i32)
+                                -> Result<(Self, Location), GenError> {
+        Self::try_generate_with_config(rng, width, height, &WorldMapConfig::default())
+    }
+    // Generates a map from a seed, picking a random one if none is given.
+    // The seed is returned so the same map can be generated again.
+    pub fn generate_seeded(seed: Option<u64>, width: i32, height: i32) -> (Self, Location, u64) {
+        let seed = match seed {
+            Some(seed) => seed,
+            None => rand::random::<u64>()
+        };
+        let (world, start) = Self::generate(&mut seeded_rng(seed), width, height);
+
+        (world, start, seed)
+    }
+    pub fn generate_with_config<R: Rng>(rng: &mut R, width: i32, height: i32,
+                                        config: &WorldMapConfig) -> (Self, Location) {
+        Self::try_generate_with_config(rng, width, height, config).unwrap()
+    }
+    pub fn try_generate_with_config<R: Rng>(rng: &mut R, width: i32, height: i32,
+                                            config: &WorldMapConfig)
+                                            -> Result<(Self, Location), GenError> {
+        WorldMapBuilder::preset(width, height, config).build(rng)
+    }
+    // Generates one level of a multi-level dungeon: the start is marked with
+    // up stairs and the way down is in another room. Returns the map, the
+    // start and the down stairs.
+    pub fn generate_level<R: Rng>(rng: &mut R, width: i32, height: i32,
+                                  config: &WorldMapConfig)
+                                  -> Result<(Self, Location, Location), GenError> {
+        let (world, _, stairs) = WorldMapBuilder::preset(width, height, config)
+            .place_stairs()
+            .run(rng)?;
+        let (up, down) = stairs.unwrap();
+
+        Ok((world, up, down))
+    }
+    // Like generate, but paints corridors with Terrain::Debug so the paths
+    // found while connecting rooms can be seen.
+    pub fn generate_with_debug<R: Rng>(rng: &mut R, width: i32, height: i32) -> (Self, Location) {
+        let mut builder = WorldMapBuilder::preset(width, height, &WorldMapConfig::default());
+        builder.debug = true;
+        builder.build(rng).unwrap()
+    }
+    // Tries to place up to `count` more rooms at random without overlapping
+    // the ones already placed. If there are still no rooms at all after
+    // that, the smallest possible room is forced in.
+    fn add_features<R: Rng>(&mut self, rng: &mut R, features: &mut Vec<Feature>,
+                            config: &WorldMapConfig, count: usize) {
+        let (width, height) = (self.width, self.height);
 
         // Generate random features.
-        let mut feature_generators: Vec<(Box<Fn(&mut R) -> FeatureBuilder>, u32)            > = vec![
-                    (Box::new(|rng: &mut R| {
-                        let i = rng.gen_range::<i32>(3,15);
-                        let j = rng.gen_range::<i32>(3,15);
-                        FeatureBuilder::room(i,j)
-                    }), 1),
-                    (Box::new(|rng: &mut R| {
-                        let j = rng.gen_range::<i32>(3,15);
-                        FeatureBuilder::room_circle(j)
-                    }), 1)
-                ];
+        let (min_size, max_size) = (config.min_room_size, config.max_room_size);
+        let sizes = config.room_size_distribution;
+        let feature_generators: Vec<(Box<Fn(&mut R) -> FeatureBuilder>, u32)> =
+            config.room_shapes.iter()
+                .filter(|&&(_, weight)| weight > 0)
+                .map(|&(shape, weight)| {
+                    let generator: Box<Fn(&mut R) -> FeatureBuilder> = match shape {
+                        RoomShape::Rectangular => Box::new(move |rng: &mut R| {
+                            let i = sizes.sample(rng, min_size, max_size);
+                            let j = sizes.sample(rng, min_size, max_size);
+                            FeatureBuilder::room(i,j)
+                        }),
+                        RoomShape::Circular => Box::new(move |rng: &mut R| {
+                            let j = sizes.sample(rng, min_size, max_size);
+                            FeatureBuilder::room_circle(j)
+                        }),
+                        RoomShape::Elliptical => Box::new(move |rng: &mut R| {
+                            let i = sizes.sample(rng, min_size, max_size);
+                            let j = sizes.sample(rng, min_size, max_size);
+                            FeatureBuilder::room_ellipse(i,j)
+                        })
+                    };
+                    (generator, weight)
+                })
+                .collect();
         let feature_table = RandomTable::new(feature_generators);
-        let mut features: Vec<Feature> = Vec::new();
-        'outer: while features.len() < 12 {
-            let feature_builder = feature_table.generate(rng);
-            let feature_x = rng.gen_range::<i32>(0, width);
-            let feature_y = rng.gen_range::<i32>(0, height);
-            let feature = feature_builder
-                .vert_align(VerticalAlignment::Top)
-                .horiz_align(HorizontalAlignment::Left)
-                .location(Location::new(feature_x, feature_y))
-                .build();
+        let target = features.len() + count;
+        let needed = config.min_rooms.min(target);
+        let mut padding = config.room_padding;
+        loop {
+            'outer: for _ in 0..config.room_attempts {
+                if features.len() >= target { break; }
+                let feature_builder =
+                    if config.prefabs.len() > 0 && rng.gen::<f64>() < config.prefab_chance {
+                        config.prefabs.iter().random(rng).builder()
+                    } else {
+                        feature_table.generate(rng).wall_thickness(config.wall_thickness)
+                    };
+                let feature_x = rng.gen_range::<i32>(0, width);
+                let feature_y = rng.gen_range::<i32>(0, height);
+                let feature = feature_builder
+                    .vert_align(VerticalAlignment::Top)
+                    .horiz_align(HorizontalAlignment::Left)
+                    .location(Location::new(feature_x, feature_y))
+                    .build();
+                if feature.floors().next().is_none() { continue 'outer; }
 
-            // Check if it fits in the world.
-            for &(loc, _) in feature.iter() {
-                if loc.x < 0 || loc.y < 0 || loc.x >= width || loc.y >= height {
-                    println!("Collides with map edges!");
-                    continue 'outer;
+                // Check if it fits in the world.
+                for &(loc, _) in feature.iter() {
+                    if loc.x < 0 || loc.y < 0 || loc.x >= width || loc.y >= height {
+                        continue 'outer;
+                    }
                 }
-            }
 
-            // Check if it collides with anything in the world.
-            for &(loc, _) in feature.iter() {
-                if world.get_tile(loc).terrain != Terrain::Nothing {
-                    println!("Collides with something in the world!");
+                // Check if it collides with another feature.
+                if features.iter().any(|other| match config.wall_mode {
+                    WallMode::Thick => feature.overlaps(other, padding),
+                    WallMode::Shared => feature.overlaps_sharing_walls(other)
+                }) {
                     continue 'outer;
                 }
-            }
 
-            // Draw feature.
-            for &(loc, terrain) in feature.iter() {
-                world.get_tile_mut(loc).terrain = terrain;
+                self.place_feature(&feature);
+                features.push(feature);
             }
+            if features.len() >= needed || padding == 0 { break; }
 
-            // Draw path from this to another random feature.
-            let mut should_add = true;
-            if features.len() > 0 {
-                println!("Features: {}", features.len());
-                let this_wall = feature.walls().random(rng);
-                let other_feature = features.iter().random(rng);
-                let other_wall = other_feature.walls().random(rng);
-                if world.get_tile(*this_wall).terrain != Terrain::Wall || world.get_tile(*other_wall).terrain != Terrain::Wall {
-                    for &(loc, _) in feature.iter() {
-                        world.get_tile_mut(loc).terrain = Terrain::Nothing;
-                    }
-                    continue 'outer;
-                }
-
-                // Dig out walls and find path.
-                world.get_tile_mut(*this_wall).terrain = Terrain::Nothing;
-                world.get_tile_mut(*other_wall).terrain = Terrain::Nothing;
-                println!("Searching for path from {:?} to {:?}...", this_wall, other_wall);
-                match astar::astar(ConnectRooms::new(&world, *this_wall, *other_wall)) {
-                    Some(path) => {
-                        println!("Path found!");
-                        for loc in path.iter() {
-                            world.get_tile_mut(*loc).terrain = Terrain::Floor;
-                        }
-                        world.get_tile_mut(*this_wall).terrain = Terrain::Debug;
-                        world.get_tile_mut(*other_wall).terrain = Terrain::Debug;
-                    },
-                    None => {
-                        println!("Failed to find path");
+            // Too few fit, so go round again with the rooms squeezed closer.
+            padding -= 1;
+        }
 
-                        // Put the other wall back.
-                        world.get_tile_mut(*other_wall).terrain = Terrain::Wall;
+        // Make sure there's somewhere to start: if nothing fit, force in the
+        // smallest possible room.
+        if features.len() == 0 {
+            let size = config.smallest_room_size();
+            let feature = FeatureBuilder::room(size, size)
+                .wall_thickness(config.wall_thickness)
+                .location(Location::new(rng.gen_range::<i32>(0, width - size + 1),
+                                        rng.gen_range::<i32>(0, height - size + 1)))
+                .build();
+            self.place_feature(&feature);
+            features.push(feature);
+        }
+    }
+    // Connects features along a minimum spanning tree over the gaps between
+    // their bounding boxes, with ties going to the closest centers. The tree
+    // grows one feature at a time (Prim's algorithm); then extra corridors
+    // are dug to add loops. A feature that can't be reached from the tree
+    // is undrawn, so every remaining feature, all of which are returned, is
+    // reachable from every other.
+    fn connect_all<R: Rng>(&mut self, rng: &mut R, mut unconnected: Vec<Feature>,
+                           config: &WorldMapConfig, debug: bool) -> Vec<Feature> {
+        if unconnected.len() == 0 { return unconnected; }
+        let mut features = vec![unconnected.remove(0)];
+        while unconnected.len() > 0 {
+            let mut edges = Vec::new();
+            for (i, a) in features.iter().enumerate() {
+                for (j, b) in unconnected.iter().enumerate() {
+                    let gap = a.bounds().gap(&b.bounds());
+                    edges.push((gap, a.center().manhattan(&b.center()), i, j));
+                }
+            }
+            edges.sort();
 
-                        // Undraw feature.
-                        should_add = false;
-                        for &(loc, _) in feature.iter() {
-                            world.get_tile_mut(loc).terrain = Terrain::Nothing;
+            let mut joined = None;
+            for &(_, _, i, j) in edges.iter() {
+                if self.connect_features(rng, &features[i], &unconnected[j], config, debug) {
+                    joined = Some(j);
+                    break;
+                }
+            }
+            match joined {
+                Some(j) => features.push(unconnected.remove(j)),
+                None => {
+                    for feature in unconnected.drain(..) {
+                        for &(loc, terrain) in feature.iter() {
+                            if terrain != Terrain::Nothing {
+                                self.get_tile_mut(loc).terrain = Terrain::Nothing;
+                            }
                         }
                     }
                 }
             }
-            if should_add { features.push(feature); }
         }
 
-        // Draw features.
-        /*for feature in features.iter() {
-            for wall in feature.walls() {
-                world.get_tile_mut(*wall).terrain = Terrain::Wall;
-            }
-
-            for floor in feature.floors() {
-                world.get_tile_mut(*floor).terrain = Terrain::Floor;
-            }
-        }*/
-
-        // Draw paths between rooms.
-        /*for _ in 0..20 {
-            // Pick two random walls from two random rooms.
-            let wall1 = features.iter().random(rng).walls().random(rng);
-            let wall2 = features.iter().random(rng).walls().random(rng);
-
-            // Dig out walls and find path.
-            world.get_tile_mut(*wall1).terrain = Terrain::Nothing;
-            world.get_tile_mut(*wall2).terrain = Terrain::Nothing;
-            println!("Searching for path from {:?} to {:?}...", wall1, wall2);
-            match astar::astar(ConnectRooms::new(&world, *wall1, *wall2)) {
-                Some(path) => {
-                    for loc in path.iter() {
-                        world.get_tile_mut(*loc).terrain = Terrain::Debug;
-                    }
-                },
-                None => {
-                    println!("Failed to find path");
-                    world.get_tile_mut(*wall1).terrain = Terrain::Wall;
-                    world.get_tile_mut(*wall2).terrain = Terrain::Wall;
+        // Dig extra corridors to add loops.
+        if features.len() > 1 {
+            for _ in 0..config.corridor_count {
+                let a = rng.gen_range::<usize>(0, features.len());
+                let b = rng.gen_range::<usize>(0, features.len());
+                if a != b {
+                    self.connect_features(rng, &features[a], &features[b], config, debug);
                 }
             }
-        }*/
+        }
 
-        // Pick a random floor in a random room to start on.
-        let starting_loc = *features.iter().random(rng).floors().random(rng);
-        let tiles2: Vec<_> = ::std::iter::repeat(Terrain::Nothing)
-            .take((width * height) as usize)
-            .map(|terrain| Tile::new(terrain))
-            .collect();
-        let mut world2 = WorldMap {width: width, height: height, tiles: tiles2};
-        for i in width-10..width-5 {
-            for j in 0..height {
-                world2.get_tile_mut(Location::new(i, j)).terrain = Terrain::Debug;
+        features
+    }
+    // Replaces each floor tile with a terrain picked at random by weight.
+    fn vary_floors<R: Rng>(&mut self, rng: &mut R, weights: &[(Terrain, u32)]) {
+        let total: u32 = weights.iter().map(|&(_, weight)| weight).sum();
+        if total == 0 { return; }
+        for tile in self.tiles.iter_mut().filter(|tile| tile.terrain == Terrain::Floor) {
+            let mut roll = rng.gen_range::<u32>(0, total);
+            for &(terrain, weight) in weights.iter() {
+                if roll < weight {
+                    tile.terrain = terrain;
+                    break;
+                }
+                roll -= weight;
             }
         }
+    }
+    // Puts up stairs in one feature and down stairs in another, returning
+    // where they went.
+    fn place_stairs<R: Rng>(&mut self, rng: &mut R,
+                            features: &[Feature]) -> Result<(Location, Location), GenError> {
+        if features.len() < 2 { return Err(GenError::TooFewRooms); }
 
-        (world, starting_loc)
+        let up_room = rng.gen_range::<usize>(0, features.len());
+        let down_room = (up_room + rng.gen_range::<usize>(1, features.len())) % features.len();
+        let up = features[up_room].random_floor(rng);
+        let down = features[down_room].random_floor(rng);
+        self.get_tile_mut(up).terrain = Terrain::StairsUp;
+        self.get_tile_mut(down).terrain = Terrain::StairsDown;
+
+        Ok((up, down))
     }
-    pub fn tiles(&self) -> TileIterator {
-        TileIterator::new(&self.tiles, self.width)
+    pub fn width(&self) -> i32 {
+        self.width
     }
-    pub fn get_tile(&self, loc: Location) -> &Tile {
-        let index = (loc.y * self.width + loc.x) as usize;
-        assert!(index < self.tiles.len());
-        &self.tiles[index]
+    pub fn height(&self) -> i32 {
+        self.height
     }
-    pub fn get_tile_mut(&mut self, loc: Location) -> &mut Tile {
-        let index = (loc.y * self.width + loc.x) as usize;
-        assert!(index < self.tiles.len());
-        &mut self.tiles[index]
+    // Bounding boxes of the rooms placed during generation, all connected
+    // to each other. Empty for maps built by hand.
+    pub fn rooms(&self) -> &[Rect] {
+        &self.rooms
     }
-    fn get_adjacent(&self, loc: Location) -> Vec<Location> {
-        let mut adjacent = Vec::new();
-        if loc.x > 0 { adjacent.push(Location::new(loc.x - 1, loc.y)); }
-        if loc.y > 0 { adjacent.push(Location::new(loc.x, loc.y - 1)); }
-        if loc.x < self.width - 1 { adjacent.push(Location::new(loc.x + 1, loc.y)); }
-        if loc.y < self.height - 1 { adjacent.push(Location::new(loc.x, loc.y + 1)); }
-
-        return adjacent;
+    // Records a room laid out by some other generator or by hand.
+    pub fn add_room(&mut self, room: Rect) {
+        self.rooms.push(room);
     }
-}
+    // The smallest rectangle holding every tile that isn't Terrain::Nothing,
+    // or None if there are none, for centering a camera on what was dug.
+    pub fn content_bounds(&self) -> Option<Rect> {
+        let mut bounds: Option<(Location, Location)> = None;
+        for (_, loc) in self.tiles().filter(|&(tile, _)| tile.terrain != Terrain::Nothing) {
+            bounds = Some(match bounds {
+                Some((min, max)) => (Location::new(min.x.min(loc.x), min.y.min(loc.y)),
+                                     Location::new(max.x.max(loc.x), max.y.max(loc.y))),
+                None => (loc, loc)
+            });
+        }
 
-#[derive(Copy, Clone, Eq, PartialEq, Hash)]
-pub struct Location {
-    pub x: i32,
-    pub y: i32
-}
+        bounds.map(|(min, max)| Rect::new(min.x, min.y, max.x - min.x + 1, max.y - min.y + 1))
+    }
+    // Whether some room's bounding box holds both locations. Corridors
+    // belong to no room, so a corridor tile isn't in the same room as
+    // anything, itself included.
+    pub fn same_room(&self, a: Location, b: Location) -> bool {
+        self.rooms.iter().any(|room| room.contains(a) && room.contains(b))
+    }
+    // Labels locations with a name, like "boss_arena", that gameplay code
+    // can look them up by later. Locations outside the map are skipped.
+    pub fn tag_region(&mut self, locs: &[Location], tag: &str) {
+        let in_bounds: Vec<_> = locs.iter().cloned().filter(|loc| self.in_bounds(*loc)).collect();
+        self.tags.entry(tag.to_string()).or_insert_with(HashSet::new).extend(in_bounds);
+    }
+    // Locations with the given tag, in row-major order.
+    pub fn tiles_with_tag(&self, tag: &str) -> Vec<Location> {
+        let mut locs: Vec<_> = match self.tags.get(tag) {
+            Some(locs) => locs.iter().cloned().collect(),
+            None => return Vec::new()
+        };
+        locs.sort_by_key(|loc| (loc.y, loc.x));
 
-impl Location {
-    pub fn new(x: i32, y: i32) -> Self {
-        Location {x: x, y: y}
+        locs
     }
-    pub fn manhattan(&self, other: &Location) -> i32 {
-        let total_x = if self.x > other.x { self.x - other.x } else { other.x - self.x };
-        let total_y = if self.y > other.y { self.y - other.y } else { other.y - self.y };
+    // Renders the map as text, one line per row.
+    pub fn to_ascii(&self) -> String {
+        let mut ascii = String::with_capacity(((self.width + 1) * self.height) as usize);
+        for (_, row) in self.rows() {
+            ascii.extend(row.iter().map(|tile| tile.terrain.to_char()));
+            ascii.push('\n');
+        }
 
-        total_x + total_y
+        ascii
     }
-}
+    // Parses a map written out by to_ascii. Returns None if a character
+    // isn't a terrain symbol or the rows aren't all the same length.
+    pub fn from_ascii(ascii: &str) -> Option<Self> {
+        let rows: Vec<Vec<char>> = ascii.lines().map(|line| line.chars().collect()).collect();
+        let width = match rows.first() {
+            Some(row) => row.len() as i32,
+            None => return None
+        };
+        if width == 0 || rows.iter().any(|row| row.len() as i32 != width) { return None; }
 
-impl ::std::fmt::Debug for Location {
-    fn fmt(&self, f: &mut ::std::fmt::Formatter) ->
-        Result<(), ::std::fmt::Error> {
-        f.write_fmt(format_args!("({}, {})", self.x, self.y))
+        let mut world = WorldMap::new(width, rows.len() as i32);
+        for (y, row) in rows.iter().enumerate() {
+            for (x, c) in row.iter().enumerate() {
+                let terrain = match Terrain::from_char(*c) {
+                    Some(terrain) => terrain,
+                    None => return None
+                };
+                world.get_tile_mut(Location::new(x as i32, y as i32)).terrain = terrain;
+            }
+        }
+
+        Some(world)
     }
-}
+    // Shrinks the map for a minimap: each cell stands for a factor-by-factor
+    // block of tiles, fewer at the right and bottom edges. A block is wall
+    // if it has any wall, or else its most common walkable terrain, or else
+    // nothing. Rows come top to bottom.
+    pub fn downsample(&self, factor: i32) -> Vec<Vec<Terrain>> {
+        self.downsample_with(factor, |block| {
+            if block.contains(&Terrain::Wall) { return Terrain::Wall; }
+            let mut counts: Vec<(Terrain, usize)> = Vec::new();
+            for &terrain in block.iter().filter(|terrain| terrain.is_walkable()) {
+                match counts.iter().position(|&(counted, _)| counted == terrain) {
+                    Some(i) => counts[i].1 += 1,
+                    None => counts.push((terrain, 1))
+                }
+            }
+            // The first most common, so ties go to whichever came first.
+            counts.iter().rev().max_by_key(|&&(_, count)| count)
+                .map_or(Terrain::Nothing, |&(terrain, _)| terrain)
+        })
+    }
+    // Like downsample, but summing up each block's terrains, in row-major
+    // order, with the given function.
+    pub fn downsample_with<F>(&self, factor: i32, summarize: F) -> Vec<Vec<Terrain>>
+        where F: Fn(&[Terrain]) -> Terrain {
+        assert!(factor >= 1, "Can't downsample by less than 1.");
+        let width = (self.width + factor - 1) / factor;
+        let height = (self.height + factor - 1) / factor;
+        (0..height).map(|y| (0..width).map(|x| {
+            let block: Vec<_> = self.tiles_in_rect(Location::new(x * factor, y * factor),
+                                                   factor, factor)
+                .map(|(tile, _)| tile.terrain)
+                .collect();
+            summarize(&block)
+        }).collect()).collect()
+    }
+    // A hash of the map's size, terrain and where entities are, the same
+    // from run to run, for checking that a map came out as expected.
+    // Visibility, rooms and entity ids don't count.
+    pub fn fingerprint(&self) -> u64 {
+        let mut hash = FNV_OFFSET_BASIS;
+        let mut feed = |bytes: &[u8]| {
+            for &byte in bytes {
+                hash = (hash ^ byte as u64).wrapping_mul(FNV_PRIME);
+            }
+        };
+        feed(&[(self.width >> 24) as u8, (self.width >> 16) as u8,
+               (self.width >> 8) as u8, self.width as u8]);
+        feed(&[(self.height >> 24) as u8, (self.height >> 16) as u8,
+               (self.height >> 8) as u8, self.height as u8]);
+        let mut count = Vec::new();
+        for tile in self.tiles.iter() {
+            // The count as a varint, so small counts stay one byte and big
+            // ones don't wrap around.
+            count.clear();
+            write_varint(&mut count, tile.entities.len() as u64);
+            feed(&[tile.terrain.to_char() as u8]);
+            feed(&count);
+            for entity in tile.entities.iter() {
+                feed(&[entity.blocks as u8]);
+            }
+        }
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
-pub enum Terrain {
-    Debug,
-    Nothing,
-    Floor,
-    Wall
-}
+        hash
+    }
+    // Packs the map into a compact binary form for save files: runs of
+    // identical tiles, then the entities, rooms and tags. Components aren't
+    // saved, as with serde.
+    pub fn to_rle_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_varint(&mut bytes, self.width as u64);
+        write_varint(&mut bytes, self.height as u64);
+        write_varint(&mut bytes, self.next_entity_id);
 
-#[derive(Copy, Clone, Debug)]
-pub struct Entity {
-    id: u64
-}
+        let mut runs: Vec<((Terrain, Visibility), u64)> = Vec::new();
+        for tile in self.tiles.iter() {
+            let key = (tile.terrain, tile.visibility);
+            if let Some(last) = runs.last_mut() {
+                if last.0 == key { last.1 += 1; continue; }
+            }
+            runs.push((key, 1));
+        }
+        write_varint(&mut bytes, runs.len() as u64);
+        for ((terrain, visibility), length) in runs {
+            bytes.push(terrain.to_char() as u8);
+            bytes.push(visibility as u8);
+            write_varint(&mut bytes, length);
+        }
 
-pub struct Tile {
-    pub terrain: Terrain,
-    pub entities: Vec<Entity>
-}
+        write_varint(&mut bytes, self.entities().count() as u64);
+        for (entity, loc) in self.entities() {
+            write_varint(&mut bytes, entity.id);
+            write_varint(&mut bytes, self.index_of(loc) as u64);
+            bytes.push(entity.blocks as u8);
+        }
 
-impl Tile {
-    pub fn new(terrain: Terrain) -> Self {
-        Tile {terrain: terrain, entities: Vec::new()}
-    }
-}
+        write_varint(&mut bytes, self.rooms.len() as u64);
+        for room in self.rooms.iter() {
+            for &n in [room.x, room.y, room.width, room.height].iter() {
+                write_varint(&mut bytes, zigzag(n));
+            }
+        }
 
-pub struct TileIterator<'a> {
-    tiles: &'a [Tile],
-    width: i32,
-    curr: usize
-}
+        let mut tags: Vec<_> = self.tags.keys().collect();
+        tags.sort();
+        write_varint(&mut bytes, tags.len() as u64);
+        for tag in tags {
+            write_varint(&mut bytes, tag.len() as u64);
+            bytes.extend(tag.as_bytes());
+            let locs = self.tiles_with_tag(tag);
+            write_varint(&mut bytes, locs.len() as u64);
+            for loc in locs {
+                write_varint(&mut bytes, self.index_of(loc) as u64);
+            }
+        }
 
-impl<'a> TileIterator<'a> {
-    pub fn new(tiles: &'a [Tile], width: i32) -> Self {
-        TileIterator {tiles: tiles, width: width, curr: 0}
+        bytes
     }
-}
+    // Unpacks a map written by to_rle_bytes. Returns None if the bytes are
+    // cut short, have anything left over or don't describe a map that
+    // passes validate.
+    pub fn from_rle_bytes(bytes: &[u8]) -> Option<Self> {
+        let mut bytes = bytes.iter().cloned();
+        let width = read_varint(&mut bytes)?;
+        let height = read_varint(&mut bytes)?;
+        if width == 0 || height == 0 || width > i32::max_value() as u64 ||
+            height > i32::max_value() as u64 {
+            return None;
+        }
+        let next_entity_id = read_varint(&mut bytes)?;
 
-impl<'a> Iterator for TileIterator<'a> {
-    type Item = (&'a Tile, Location);
+        // Read the runs before making the map, so a header claiming a huge
+        // map can't get one allocated without the tiles to back it up.
+        let size = width.checked_mul(height)?;
+        let tile_bytes = size.checked_mul(::std::mem::size_of::<Tile>() as u64)?;
+        if tile_bytes > isize::max_value() as u64 { return None; }
+        let mut runs = Vec::new();
+        let mut total: u64 = 0;
+        for _ in 0..read_varint(&mut bytes)? {
+            let terrain = Terrain::from_char(bytes.next()? as char)?;
+            let visibility = match bytes.next()? {
+                0 => Visibility::Unseen,
+                1 => Visibility::Remembered,
+                2 => Visibility::Visible,
+                _ => return None
+            };
+            let length = read_varint(&mut bytes)?;
+            total = total.checked_add(length)?;
+            if total > size { return None; }
+            runs.push((terrain, visibility, length as usize));
+        }
+        if total != size { return None; }
 
-    fn next(&mut self) -> Option<(&'a Tile, Location)> {
-        if self.curr < self.tiles.len() {
-            let this = self.curr as i32;
-            self.curr += 1;
-            Some((
-                &self.tiles[this as usize],
-                Location {
-                    x: this % self.width,
-                    y: this / self.width
-                }))
-        } else { None }
-    }
-}
+        let mut world = WorldMap::new(width as i32, height as i32);
+        world.next_entity_id = next_entity_id;
+        let mut index = 0;
+        for (terrain, visibility, length) in runs {
+            for tile in world.tiles[index..index + length].iter_mut() {
+                tile.terrain = terrain;
+                tile.visibility = visibility;
+            }
+            index += length;
+        }
 
-// GENERATION STUFF.
-// A feature in the world, consisting of some arrangement of terrain.
-// Features consist of relative coordinates; they can be placed at any
-// arbitrary location.
-#[derive(Clone, Debug)]
-struct Feature {
-    components: Vec<(Location, Terrain)>
-}
+        for _ in 0..read_varint(&mut bytes)? {
+            let id = read_varint(&mut bytes)?;
+            let index = read_varint(&mut bytes)? as usize;
+            let blocks = match bytes.next()? {
+                0 => false,
+                1 => true,
+                _ => return None
+            };
+            if index >= world.tiles.len() || world.entity_locations.contains_key(&id) {
+                return None;
+            }
+            world.tiles[index].entities.push(Entity {id: id, blocks: blocks});
+            let loc = world.location_of(index);
+            world.entity_locations.insert(id, loc);
+        }
 
-impl Feature {
-    pub fn new(components: Vec<(Location, Terrain)>) -> Self {
-        Feature { components: components }
-    }
-    pub fn overlaps(&self, other: &Feature) -> bool {
-        for a in self.components.iter() {
-            for b in other.components.iter() {
-                if a == b { return true; }
+        for _ in 0..read_varint(&mut bytes)? {
+            let x = unzigzag(read_varint(&mut bytes)?)?;
+            let y = unzigzag(read_varint(&mut bytes)?)?;
+            let width = unzigzag(read_varint(&mut bytes)?)?;
+            let height = unzigzag(read_varint(&mut bytes)?)?;
+            world.rooms.push(Rect::new(x, y, width, height));
+        }
+
+        for _ in 0..read_varint(&mut bytes)? {
+            let length = read_varint(&mut bytes)? as usize;
+            let name: Vec<u8> = bytes.by_ref().take(length).collect();
+            let tag = String::from_utf8(name).ok()?;
+            if tag.len() != length { return None; }
+            let mut locs = HashSet::new();
+            for _ in 0..read_varint(&mut bytes)? {
+                let index = read_varint(&mut bytes)? as usize;
+                if index >= world.tiles.len() { return None; }
+                locs.insert(world.location_of(index));
             }
+            world.tags.insert(tag, locs);
         }
 
-        return false;
+        if bytes.next().is_some() { return None; }
+        world.validate().ok()?;
+        Some(world)
+    }
+    // The map turned a quarter turn clockwise, tiles and entities alike.
+    pub fn rotated_90(&self) -> Self {
+        let height = self.height;
+        self.transformed(self.height, self.width, |loc| Location::new(height - 1 - loc.y, loc.x),
+                         |direction| direction.clockwise())
+    }
+    // The map mirrored left to right.
+    pub fn flipped_horizontal(&self) -> Self {
+        let width = self.width;
+        self.transformed(self.width, self.height, |loc| Location::new(width - 1 - loc.x, loc.y),
+                         |direction| match direction {
+                             Direction::East => Direction::West,
+                             Direction::West => Direction::East,
+                             _ => direction
+                         })
     }
-    /*pub fn width(&self) -> i32 {
-        if self.components.len() == 0 {
-            return 0;
+    // The map mirrored top to bottom.
+    pub fn flipped_vertical(&self) -> Self {
+        let height = self.height;
+        self.transformed(self.width, self.height, |loc| Location::new(loc.x, height - 1 - loc.y),
+                         |direction| match direction {
+                             Direction::North => Direction::South,
+                             Direction::South => Direction::North,
+                             _ => direction
+                         })
+    }
+    // Copies the map into one of the given size, moving the tile at each
+    // location to wherever `transform` sends it and turning ledges with
+    // `turn` to match. Components can't be copied, so entities in the copy
+    // have none.
+    fn transformed<F, G>(&self, width: i32, height: i32, transform: F, turn: G) -> Self
+        where F: Fn(Location) -> Location, G: Fn(Direction) -> Direction {
+        let mut world = WorldMap::new(width, height);
+        for (tile, loc) in self.tiles() {
+            let copy = world.get_tile_mut(transform(loc));
+            *copy = tile.clone();
+            if let Terrain::Ledge(direction) = tile.terrain {
+                copy.terrain = Terrain::Ledge(turn(direction));
+            }
         }
+        world.next_entity_id = self.next_entity_id;
+        world.entity_locations = self.entity_locations.iter()
+            .map(|(id, loc)| (*id, transform(*loc)))
+            .collect();
+        world.rooms = self.rooms.iter()
+            .map(|room| {
+                let corner = Location::new(room.x + room.width - 1, room.y + room.height - 1);
+                Rect::bounding(&[transform(Location::new(room.x, room.y)), transform(corner)])
+            })
+            .collect();
+        world.tags = self.tags.iter()
+            .map(|(tag, locs)| (tag.clone(), locs.iter().map(|loc| transform(*loc)).collect()))
+            .collect();
 
-        self.components.iter().map(|c| c.0.x).max().unwrap() -
-            self.components.iter().map(|c| c.0.x).min().unwrap() + 1
+        world
     }
-    pub fn height(&self) -> i32 {
-        if self.components.len() == 0 {
-            return 0;
+    // A new map holding just the given part of this one, with its top left
+    // corner moved to the origin. Any of the rectangle hanging off the map
+    // comes out as Terrain::Nothing. Entities and tags outside it are
+    // dropped and rooms cut down to what's left of them; as with
+    // rotated_90, the entities that remain lose their components. None if
+    // the rectangle is empty, since a map needs at least one tile.
+    pub fn crop(&self, rect: Rect) -> Option<Self> {
+        if rect.is_empty() { return None; }
+        let origin = Offset::new(rect.x, rect.y);
+        let mut world = WorldMap::new(rect.width, rect.height);
+        for (tile, loc) in self.tiles().filter(|&(_, loc)| rect.contains(loc)) {
+            *world.get_tile_mut(loc - origin) = tile.clone();
         }
+        world.next_entity_id = self.next_entity_id;
+        world.entity_locations = self.entity_locations.iter()
+            .filter(|&(_, loc)| rect.contains(*loc))
+            .map(|(id, loc)| (*id, *loc - origin))
+            .collect();
+        world.rooms = self.rooms.iter()
+            .map(|room| Rect::new(room.x - rect.x, room.y - rect.y, room.width, room.height)
+                 .clamp_to(rect.width, rect.height))
+            .filter(|room| !room.is_empty())
+            .collect();
+        world.tags = self.tags.iter()
+            .map(|(tag, locs)| {
+                let kept: HashSet<_> = locs.iter()
+                    .filter(|loc| rect.contains(**loc))
+                    .map(|loc| *loc - origin)
+                    .collect();
+                (tag.clone(), kept)
+            })
+            .filter(|&(_, ref locs)| !locs.is_empty())
+            .collect();
 
-        self.components.iter().map(|c| c.0.y).max().unwrap() -
-            self.components.iter().map(|c| c.0.y).min().unwrap() + 1
-    }*/
-    pub fn iter(&self) -> ::std::slice::Iter<(Location, Terrain)> {
-        self.components.iter()
+        Some(world)
     }
-    pub fn walls<'a>(&'a self) -> Box<Iterator<Item=&'a Location> + 'a> {
-        Box::new(self.components.iter()
-            .filter(|c| c.1 == Terrain::Wall)
-            .map(|c| &c.0))
+    // Turns empty space next to anything walkable, diagonals included, into
+    // wall, so corridors and other bare floor end up fully enclosed.
+    pub fn wrap_walls(&mut self) {
+        let exposed: Vec<_> = self.tiles()
+            .filter(|&(tile, _)| tile.terrain == Terrain::Nothing)
+            .map(|(_, loc)| loc)
+            .filter(|loc| self.neighbors(*loc, true).iter()
+                .any(|next| self.get_tile(*next).terrain.is_walkable()))
+            .collect();
+        for loc in exposed {
+            self.get_tile_mut(loc).terrain = Terrain::Wall;
+        }
     }
-    pub fn floors<'a>(&'a self) -> Box<Iterator<Item=&'a Location> + 'a> {
-        Box::new(self.components.iter()
-            .filter(|c| c.1 == Terrain::Floor)
-            .map(|c| &c.0))
+    // Digs an L-shaped tunnel, horizontal then vertical, turning any wall
+    // or empty space on the way into floor. Ends outside the map are moved
+    // to its nearest edge.
+    pub fn carve_tunnel(&mut self, from: Location, to: Location) {
+        let clamp = |loc: Location| Location::new(loc.x.max(0).min(self.width - 1),
+                                                  loc.y.max(0).min(self.height - 1));
+        let (from, to) = (clamp(from), clamp(to));
+        let step_x = if to.x > from.x { 1 } else { -1 };
+        let step_y = if to.y > from.y { 1 } else { -1 };
+        let mut loc = from;
+        loop {
+            match self.get_tile(loc).terrain {
+                Terrain::Wall | Terrain::Nothing => self.get_tile_mut(loc).terrain = Terrain::Floor,
+                _ => {}
+            }
+            if loc.x != to.x {
+                loc.x += step_x;
+            } else if loc.y != to.y {
+                loc.y += step_y;
+            } else {
+                break;
+            }
+        }
     }
-}
-
-#[derive(Clone, Copy, Debug)]
-enum HorizontalAlignment {
-    Left,
-    Center,
-    Right
-}
-
-#[derive(Clone, Copy, Debug)]
-enum VerticalAlignment {
-    Top,
-    Center,
-    Bottom
-}
-
-// Build features! Take the raw feature shape and translate it
-// according to the given alignment and absolute location.
-#[derive(Clone, Debug)]
-struct FeatureBuilder {
-    components: Vec<(Location, Terrain)>,
-    location: Location,
-    horiz_align: HorizontalAlignment,
-    vert_align: VerticalAlignment
-}
+    // Draws a plain rectangular room, walls around the edge and floor inside,
+    // and records it as one of the map's rooms. Any part outside the map is
+    // left off.
+    pub fn carve_room(&mut self, rect: Rect) {
+        let visible = rect.clamp_to(self.width, self.height);
+        if visible.is_empty() { return; }
 
-impl FeatureBuilder {
-    pub fn new(components: Vec<(Location, Terrain)>) -> Self {
-        assert!(components.len() > 0);
-        FeatureBuilder {
-            components: components,
-            location: Location::new(0, 0),
-            horiz_align: HorizontalAlignment::Left,
-            vert_align: VerticalAlignment::Top
+        // Only the visible part is drawn, so a huge rect costs no more than
+        // the map it lands on.
+        let right = rect.x.saturating_add(rect.width) - 1;
+        let bottom = rect.y.saturating_add(rect.height) - 1;
+        for y in visible.y..visible.y + visible.height {
+            for x in visible.x..visible.x + visible.width {
+                let edge = x == rect.x || x == right || y == rect.y || y == bottom;
+                self.set_terrain(Location::new(x, y),
+                                 if edge { Terrain::Wall } else { Terrain::Floor });
+            }
         }
+        self.add_room(visible);
     }
-    pub fn room(width: i32, height: i32) -> Self {
-        let mut components = Vec::new();
-        for x in 0..width {
-            for y in 0..height {
-                let terrain =
-                    if x == 0 || x == width - 1 || y == 0 || y == height - 1 {
-                        Terrain::Wall
-                    } else {
-                        Terrain::Floor
-                    };
-                components.push((Location::new(x, y), terrain));
+    // Writes a feature's terrain into the map. Components outside the map
+    // are skipped, as are Nothing components, so a feature's empty corners
+    // don't erase what's already there.
+    pub fn place_feature(&mut self, feature: &Feature) {
+        for &(loc, terrain) in feature.iter() {
+            if terrain == Terrain::Nothing { continue; }
+            if self.terrain_at(loc).map_or(false, |current| current != terrain) {
+                self.get_tile_mut(loc).terrain = terrain;
             }
         }
+    }
+    // Creates a new entity on the given tile. It blocks other blocking
+    // entities from moving onto its tile, like a monster would.
+    pub fn spawn_entity(&mut self, loc: Location) -> Entity {
+        self.spawn(loc, true)
+    }
+    // Creates a new entity that doesn't block movement, like an item.
+    pub fn spawn_item(&mut self, loc: Location) -> Entity {
+        self.spawn(loc, false)
+    }
+    fn spawn(&mut self, loc: Location, blocks: bool) -> Entity {
+        let entity = Entity { id: self.next_entity_id, blocks: blocks };
+        self.get_tile_mut(loc).entities.push(entity);
+        self.entity_locations.insert(entity.id, loc);
+        self.next_entity_id += 1;
 
-        FeatureBuilder::new(components)
+        entity
     }
-    pub fn room_circle(radius: i32) -> Self {
-        let mut components = Vec::new();
-        for x in -radius..radius+1 {
-            for y in -radius..radius+1 {
-                let terrain = 
-                    if FeatureBuilder::distance_from_center(x,y) == radius {
-                        Terrain::Wall
-                    }
-                    else if FeatureBuilder::distance_from_center(x,y) < radius {
-                        Terrain::Floor
-                    }
-                    else {
-                        Terrain::Nothing
-                    };
-                components.push((Location::new(x, y), terrain));
+    // Removes an entity from the given tile, returning whether it was there.
+    pub fn remove_entity(&mut self, loc: Location, id: u64) -> bool {
+        let removed = {
+            let index = self.entities_at(loc).iter().position(|entity| entity.id == id);
+            match index {
+                Some(index) => {
+                    self.get_tile_mut(loc).entities.remove(index);
+                    true
+                },
+                None => false
             }
+        };
+        if removed {
+            self.entity_locations.remove(&id);
+            self.components.remove_entity(id);
         }
 
-        FeatureBuilder::new(components)
+        removed
     }
-    pub fn distance_from_center(xi: i32,yi: i32) -> i32 {
-        let c: i32 = (xi * xi) + (yi * yi);
-        let c = (c as f64).sqrt();
-        c as i32
+    // Whether one step from `from` may end on `to`: the destination is on
+    // the map, can be walked on, and isn't a ledge entered from the wrong
+    // side. Other entities aren't considered.
+    pub fn can_step(&self, from: Location, to: Location) -> bool {
+        self.try_get_tile(to).map_or(false, |tile| {
+            tile.terrain.is_walkable() && (from == to || tile.terrain.can_enter(to - from))
+        })
     }
-    pub fn location(mut self, loc: Location) -> Self {
-        self.location = loc;
-        self
+    // Moves an entity between tiles. Nothing changes if the move fails.
+    pub fn move_entity(&mut self, id: u64, from: Location, to: Location) -> Result<(), MoveError> {
+        let index = match self.try_get_tile(from)
+            .and_then(|tile| tile.entities.iter().position(|entity| entity.id == id)) {
+            Some(index) => index,
+            None => return Err(MoveError::NotFound)
+        };
+        match self.try_get_tile(to) {
+            Some(tile) => if !tile.terrain.is_walkable() { return Err(MoveError::NotWalkable); },
+            None => return Err(MoveError::OutOfBounds)
+        }
+        if from != to && !self.get_tile(to).terrain.can_enter(to - from) {
+            return Err(MoveError::WrongWay);
+        }
+        if self.get_tile(from).entities[index].blocks && from != to && !self.is_passable(to) {
+            return Err(MoveError::Blocked);
+        }
+
+        let entity = self.get_tile_mut(from).entities.remove(index);
+        self.get_tile_mut(to).entities.push(entity);
+        self.entity_locations.insert(id, to);
+
+        Ok(())
     }
-    pub fn horiz_align(mut self, align: HorizontalAlignment) -> Self {
-        self.horiz_align = align;
-        self
+    // Entities on a tile; none if the location is outside the map.
+    pub fn entities_at(&self, loc: Location) -> &[Entity] {
+        match self.try_get_tile(loc) {
+            Some(tile) => &tile.entities,
+            None => &[]
+        }
     }
-    pub fn vert_align(mut self, align: VerticalAlignment) -> Self {
-        self.vert_align = align;
-        self
+    // Floor tiles inside the rectangle with no entity on them, in row-major
+    // order.
+    pub fn free_floors_in(&self, rect: Rect) -> Vec<Location> {
+        self.tiles_in_rect(Location::new(rect.x, rect.y), rect.width, rect.height)
+            .filter(|&(tile, _)| tile.terrain == Terrain::Floor && tile.entities.is_empty())
+            .map(|(_, loc)| loc)
+            .collect()
     }
-    pub fn build(&self) -> Feature {
-        let horiz = match self.horiz_align {
-            HorizontalAlignment::Left => {
-                self.location.x - Self::calc_min_x(&self.components)
-            },
-            HorizontalAlignment::Center => {
-                self.location.x - (Self::calc_min_x(&self.components) +
-                    (Self::calc_max_x(&self.components) -
-                    Self::calc_min_x(&self.components) + 1) / 2)
-            },
-            HorizontalAlignment::Right => {
-                self.location.x - Self::calc_max_x(&self.components)
+    // Every entity on the map with its location, in row-major order and, on
+    // each tile, the order they arrived in.
+    pub fn entities<'a>(&'a self) -> Box<Iterator<Item=(Entity, Location)> + 'a> {
+        Box::new(self.tiles()
+            .flat_map(|(tile, loc)| tile.entities.iter().map(move |entity| (*entity, loc))))
+    }
+    // Spawns a random number of entities in each room, on empty floor tiles
+    // only, and returns them with where they were put. Rooms with too little
+    // space get as many as fit.
+    pub fn populate<R: Rng>(&mut self, rng: &mut R,
+                            spawns_per_room: Range<i32>) -> Vec<(Entity, Location)> {
+        self.populate_where(rng, spawns_per_room, |_| true)
+    }
+    // Like populate, but leaves every tile within safe_radius steps of
+    // start (diagonals counting as one) empty, so the player doesn't open
+    // the level next to a monster.
+    pub fn populate_with_safe_zone<R: Rng>(&mut self, rng: &mut R, spawns_per_room: Range<i32>,
+                                           start: Location,
+                                           safe_radius: i32) -> Vec<(Entity, Location)> {
+        self.populate_where(rng, spawns_per_room, |loc| loc.chebyshev(&start) > safe_radius)
+    }
+    fn populate_where<R: Rng, F>(&mut self, rng: &mut R, spawns_per_room: Range<i32>,
+                                 allowed: F) -> Vec<(Entity, Location)>
+        where F: Fn(Location) -> bool {
+        let mut spawned = Vec::new();
+        for room in self.rooms.clone() {
+            let count = if spawns_per_room.start < spawns_per_room.end {
+                rng.gen_range::<i32>(spawns_per_room.start, spawns_per_room.end)
+            } else { 0 };
+            let chosen = self.free_floors_in(room).into_iter()
+                .filter(|loc| allowed(*loc))
+                .random_n(rng, count.max(0) as usize);
+            for loc in chosen {
+                spawned.push((self.spawn_entity(loc), loc));
+            }
+        }
+
+        spawned
+    }
+    // Attaches a component to an entity, returning the one of the same type
+    // it replaces. The component is dropped when the entity is removed.
+    pub fn insert_component<T: 'static>(&mut self, id: u64, component: T) -> Option<T> {
+        self.components.store_mut::<T>().insert(id, component)
+    }
+    pub fn component<T: 'static>(&self, id: u64) -> Option<&T> {
+        self.components.store::<T>().and_then(|store| store.get(id))
+    }
+    pub fn component_mut<T: 'static>(&mut self, id: u64) -> Option<&mut T> {
+        self.components.store_mut::<T>().get_mut(id)
+    }
+    pub fn remove_component<T: 'static>(&mut self, id: u64) -> Option<T> {
+        self.components.store_mut::<T>().remove(id)
+    }
+    // The components of one type for every entity that has one.
+    pub fn components<T: 'static>(&self) -> Option<&ComponentStore<T>> {
+        self.components.store::<T>()
+    }
+    // A uniformly random walkable tile with nothing on it, or None if there
+    // isn't one. Picked in a single pass over the map by reservoir sampling,
+    // so nothing is allocated, but every tile is looked at.
+    pub fn random_walkable<R: Rng>(&self, rng: &mut R) -> Option<Location> {
+        let mut chosen = None;
+        let mut seen = 0;
+        for (tile, loc) in self.tiles() {
+            if !tile.terrain.is_walkable() || !tile.entities.is_empty() { continue; }
+            seen += 1;
+            if rng.gen_range::<usize>(0, seen) == 0 {
+                chosen = Some(loc);
+            }
+        }
+
+        chosen
+    }
+    // Whether any entity, blocking or not, is on the tile.
+    pub fn is_occupied(&self, loc: Location) -> bool {
+        !self.entities_at(loc).is_empty()
+    }
+    // Whether a blocking entity could step onto the tile: the terrain is
+    // walkable and nothing blocking is already there.
+    pub fn is_passable(&self, loc: Location) -> bool {
+        match self.try_get_tile(loc) {
+            Some(tile) => tile.terrain.is_walkable() &&
+                !tile.entities.iter().any(|entity| entity.blocks),
+            None => false
+        }
+    }
+    // Where an entity is, if it's on the map.
+    pub fn find_entity(&self, id: u64) -> Option<Location> {
+        self.entity_locations.get(&id).cloned()
+    }
+    pub fn tiles(&self) -> TileIterator {
+        TileIterator::new(&self.tiles, self.width)
+    }
+    // Each row of tiles from top to bottom, with its y coordinate.
+    pub fn rows<'a>(&'a self) -> Box<Iterator<Item=(i32, &'a [Tile])> + 'a> {
+        Box::new(self.tiles.chunks(self.width as usize)
+            .enumerate()
+            .map(|(y, row)| (y as i32, row)))
+    }
+    // Tiles inside a rectangle, row by row. The rectangle is clamped to the
+    // map, so a camera hanging over the edge just sees fewer tiles.
+    pub fn tiles_in_rect<'a>(&'a self, top_left: Location, width: i32, height: i32)
+                             -> Box<Iterator<Item=(&'a Tile, Location)> + 'a> {
+        let rect = Rect::new(top_left.x, top_left.y, width, height)
+            .clamp_to(self.width, self.height);
+        Box::new((rect.y..rect.y + rect.height)
+            .flat_map(move |y| (rect.x..rect.x + rect.width).map(move |x| Location::new(x, y)))
+            .map(move |loc| (self.get_tile(loc), loc)))
+    }
+    // Locations no further than radius from center, in row-major order.
+    pub fn tiles_within(&self, center: Location, radius: i32, metric: Metric) -> Vec<Location> {
+        if radius < 0 { return Vec::new(); }
+        let top_left = Location::new(center.x - radius, center.y - radius);
+        self.tiles_in_rect(top_left, 2 * radius + 1, 2 * radius + 1)
+            .map(|(_, loc)| loc)
+            .filter(|loc| metric.within(&center, loc, radius))
+            .collect()
+    }
+    pub fn get_tile(&self, loc: Location) -> &Tile {
+        match self.try_get_tile(loc) {
+            Some(tile) => tile,
+            None => panic!("Location {:?} is outside the map.", loc)
+        }
+    }
+    pub fn get_tile_mut(&mut self, loc: Location) -> &mut Tile {
+        match self.try_get_tile_mut(loc) {
+            Some(tile) => tile,
+            None => panic!("Location {:?} is outside the map.", loc)
+        }
+    }
+    // Like get_tile, but returns None for locations outside the map.
+    pub fn try_get_tile(&self, loc: Location) -> Option<&Tile> {
+        if self.in_bounds(loc) {
+            Some(&self.tiles[self.index_of(loc)])
+        } else { None }
+    }
+    pub fn try_get_tile_mut(&mut self, loc: Location) -> Option<&mut Tile> {
+        if self.in_bounds(loc) {
+            if let Some(ref mut dirty) = self.dirty {
+                dirty.insert(loc);
             }
+            let index = self.index_of(loc);
+            Some(&mut self.tiles[index])
+        } else { None }
+    }
+    // A location on this map, or None if the coordinates fall outside it.
+    pub fn location(&self, x: i32, y: i32) -> Option<Location> {
+        let loc = Location::new(x, y);
+        if self.in_bounds(loc) { Some(loc) } else { None }
+    }
+    // The terrain at a location, or None outside the map.
+    pub fn terrain_at(&self, loc: Location) -> Option<Terrain> {
+        self.try_get_tile(loc).map(|tile| tile.terrain)
+    }
+    // Returns false, changing nothing, if the location is outside the map.
+    pub fn set_terrain(&mut self, loc: Location, terrain: Terrain) -> bool {
+        match self.terrain_at(loc) {
+            Some(current) if current == terrain => true,
+            Some(_) => { self.get_tile_mut(loc).terrain = terrain; true }
+            None => false
+        }
+    }
+    // Turns recording of changed tiles on or off. While it's on, a tile
+    // counts as dirty once it has been borrowed mutably through
+    // get_tile_mut or try_get_tile_mut, whether or not the caller then
+    // changes it. The map's own methods, set_terrain, move_entity,
+    // update_visibility and the rest, only borrow tiles they really change,
+    // so a renderer can redraw just those. Turning it off forgets them.
+    pub fn track_dirty(&mut self, enabled: bool) {
+        if !enabled {
+            self.dirty = None;
+        } else if self.dirty.is_none() {
+            self.dirty = Some(HashSet::new());
+        }
+    }
+    // The tiles that may have changed since the last call, in row-major
+    // order, clearing the record; always empty while tracking is off.
+    pub fn take_dirty(&mut self) -> Vec<Location> {
+        let mut dirty: Vec<_> = match self.dirty {
+            Some(ref mut dirty) => dirty.drain().collect(),
+            None => Vec::new()
         };
-        let vert = match self.vert_align {
-            VerticalAlignment::Top => {
-                self.location.y - Self::calc_min_y(&self.components)
-            },
-            VerticalAlignment::Center => {
-                self.location.y - (Self::calc_min_y(&self.components) +
-                    (Self::calc_max_y(&self.components) -
-                    Self::calc_min_y(&self.components) + 1) / 2)
+        dirty.sort_by_key(|loc| (loc.y, loc.x));
+
+        dirty
+    }
+    // Position of an in-bounds location in the row-major tile vector.
+    pub fn index_of(&self, loc: Location) -> usize {
+        debug_assert!(self.in_bounds(loc));
+        loc.y as usize * self.width as usize + loc.x as usize
+    }
+    pub fn location_of(&self, index: usize) -> Location {
+        debug_assert!(index < self.tiles.len());
+        location_from_index(index, self.width)
+    }
+    fn in_bounds(&self, loc: Location) -> bool {
+        loc.x >= 0 && loc.y >= 0 && loc.x < self.width && loc.y < self.height
+    }
+    // Digs a corridor from a wall of one feature to a wall of the other.
+    // Returns false, leaving the world untouched, if no path exists.
+    fn connect_features<R: Rng>(&mut self, rng: &mut R, a: &Feature, b: &Feature,
+                                config: &WorldMapConfig, debug: bool) -> bool {
+        let this_walls: Vec<_> = a.doors().into_iter()
+            .filter(|loc| self.get_tile(*loc).terrain == Terrain::Wall)
+            .collect();
+        let other_walls: Vec<_> = b.doors().into_iter()
+            .filter(|loc| self.get_tile(*loc).terrain == Terrain::Wall)
+            .collect();
+        if this_walls.len() == 0 || other_walls.len() == 0 { return false; }
+
+        // Rooms sharing a wall only need a door in it.
+        if config.wall_mode == WallMode::Shared {
+            let shared: Vec<_> = this_walls.iter()
+                .filter(|loc| other_walls.contains(loc))
+                .collect();
+            if shared.len() > 0 {
+                let door = *shared.into_iter().random(rng);
+                let terrain = if debug { Terrain::Debug } else { Terrain::Door };
+                self.get_tile_mut(door).terrain = terrain;
+                return true;
+            }
+        }
+        if config.corridor_target == CorridorTarget::Centers {
+            return self.connect_centers(a, b, config.corridor_width, debug);
+        }
+        let this_wall = this_walls.into_iter().random(rng);
+        let other_wall = other_walls.into_iter().random(rng);
+
+        // Thick walls have to be dug through to get out, though not next to
+        // the floor, or the room would get extra openings. Digging costs
+        // more so corridors don't run along inside them.
+        let buried: HashSet<_> = if config.wall_thickness > 1 {
+            let floors: HashSet<_> = a.floors().chain(b.floors()).cloned().collect();
+            a.walls().chain(b.walls())
+                .cloned()
+                .filter(|wall| {
+                    !self.neighbors(*wall, true).iter().any(|next| floors.contains(next))
+                })
+                .collect()
+        } else { HashSet::new() };
+
+        // Dig out walls and find path.
+        self.get_tile_mut(this_wall).terrain = Terrain::Nothing;
+        self.get_tile_mut(other_wall).terrain = Terrain::Nothing;
+        let walk = match config.corridor_style {
+            CorridorStyle::Straight => None,
+            CorridorStyle::Drunken => self.drunken_walk(rng, this_wall, other_wall)
+        };
+        // Corridors are dug through empty space, never through other rooms.
+        let path = walk.or_else(|| {
+            let search = GridSearch::new(self, this_wall, |loc| loc == other_wall, |loc| {
+                if self.get_tile(loc).terrain == Terrain::Nothing { Some(1) }
+                else if buried.contains(&loc) { Some(5) }
+                else { None }
+            }).toward(other_wall);
+            astar::astar(search).map(|path| path.into_iter().collect())
+        });
+        match path {
+            Some(path) => {
+                let terrain = if debug { Terrain::Debug } else { Terrain::Floor };
+                for loc in path.iter() {
+                    self.get_tile_mut(*loc).terrain = terrain;
+                }
+                self.widen_corridor(&path, config.corridor_width, terrain);
+                if !debug {
+                    self.get_tile_mut(this_wall).terrain = Terrain::Door;
+                    self.get_tile_mut(other_wall).terrain = Terrain::Door;
+                }
+                true
             },
-            VerticalAlignment::Bottom => {
-                self.location.y - Self::calc_max_y(&self.components)
+            None => {
+                self.get_tile_mut(this_wall).terrain = Terrain::Wall;
+                self.get_tile_mut(other_wall).terrain = Terrain::Wall;
+                false
             }
+        }
+    }
+    // Digs the shortest corridor between the middles of two features, never
+    // through any other, and puts doors where it goes through their walls.
+    // Returns false, leaving the world untouched, if no path exists.
+    fn connect_centers(&mut self, a: &Feature, b: &Feature, width: i32, debug: bool) -> bool {
+        let middle = |feature: &Feature| {
+            let center = feature.center();
+            if feature.floors().any(|&floor| floor == center) { Some(center) }
+            else { feature.floors().next().cloned() }
+        };
+        let (from, to) = match (middle(a), middle(b)) {
+            (Some(from), Some(to)) => (from, to),
+            _ => return false
         };
 
-        let comps = self.components.iter()
-            .map(|c| {
-                (Location::new(c.0.x + horiz, c.0.y + vert), c.1)
-            })
+        // Going through walls costs more, so the corridor goes straight
+        // through once rather than skimming along them.
+        let own: HashSet<_> = a.iter().chain(b.iter())
+            .filter(|c| c.1 != Terrain::Nothing)
+            .map(|c| c.0)
             .collect();
+        let path = {
+            let search = GridSearch::new(self, from, |loc| loc == to, |loc| {
+                match self.get_tile(loc).terrain {
+                    Terrain::Nothing => Some(1),
+                    Terrain::Wall if own.contains(&loc) => Some(3),
+                    terrain if own.contains(&loc) && terrain.is_walkable() => Some(1),
+                    _ => None
+                }
+            }).toward(to);
+            match astar::astar(search) {
+                Some(path) => path.into_iter().collect::<Vec<_>>(),
+                None => return false
+            }
+        };
 
-        Feature::new(comps)
+        // Only walls right by the floor get doors; thick walls are dug out
+        // behind them.
+        let floors: HashSet<_> = a.floors().chain(b.floors()).cloned().collect();
+        let corridor = if debug { Terrain::Debug } else { Terrain::Floor };
+        let door = if debug { Terrain::Debug } else { Terrain::Door };
+        let mut dug = Vec::new();
+        for loc in path {
+            match self.get_tile(loc).terrain {
+                Terrain::Nothing => dug.push(loc),
+                Terrain::Wall if loc.orthogonal().any(|next| floors.contains(&next)) => {
+                    self.get_tile_mut(loc).terrain = door;
+                },
+                Terrain::Wall => self.get_tile_mut(loc).terrain = corridor,
+                _ => {}
+            }
+        }
+        for loc in dug.iter() {
+            self.get_tile_mut(*loc).terrain = corridor;
+        }
+        self.widen_corridor(&dug, width, corridor);
+
+        true
+    }
+    // Widens a one-tile corridor to the given width by also digging out the
+    // tiles beside it. Only empty space is dug, so room walls stay intact.
+    fn widen_corridor(&mut self, path: &[Location], width: i32, terrain: Terrain) {
+        let (low, high) = (-(width - 1) / 2, width / 2);
+        for loc in path.iter() {
+            for dx in low..high + 1 {
+                for dy in low..high + 1 {
+                    let next = *loc + Offset::new(dx, dy);
+                    if self.terrain_at(next) == Some(Terrain::Nothing) {
+                        self.get_tile_mut(next).terrain = terrain;
+                    }
+                }
+            }
+        }
     }
+    // Wanders from one location to another through empty space, usually
+    // stepping toward the target but sometimes any which way. Gives up and
+    // returns None if the target isn't reached within a few times the
+    // straight-line number of steps.
+    fn drunken_walk<R: Rng>(&self, rng: &mut R, from: Location,
+                            to: Location) -> Option<Vec<Location>> {
+        let mut path = vec![from];
+        let mut at = from;
+        for _ in 0..DRUNKEN_STEPS_PER_TILE * from.manhattan(&to) {
+            if at == to { break; }
+            let offset = if rng.gen_range::<u32>(0, 3) < 2 {
+                ORTHOGONAL.iter().cloned()
+                    .filter(|offset| (at + *offset).manhattan(&to) < at.manhattan(&to))
+                    .random(rng)
+            } else {
+                ORTHOGONAL.iter().cloned().random(rng)
+            };
+            let next = at + offset;
+            if self.try_get_tile(next).map_or(false, |tile| tile.terrain == Terrain::Nothing) {
+                at = next;
+                path.push(at);
+            }
+        }
 
-    fn calc_min_x(components: &[(Location, Terrain)]) -> i32 {
-        components.iter().map(|c| c.0.x).min().unwrap()
+        if at == to { Some(path) } else { None }
+    }
+    // Shortest walkable path between two locations, including both ends.
+    pub fn find_path(&self, from: Location, to: Location) -> Option<Vec<Location>> {
+        self.find_path_with_movement(from, to, Movement::Orthogonal)
     }
-    fn calc_max_x(components: &[(Location, Terrain)]) -> i32 {
-        components.iter().map(|c| c.0.x).max().unwrap()
+    pub fn find_path_with_movement(&self, from: Location, to: Location,
+                                   movement: Movement) -> Option<Vec<Location>> {
+        let search = GridSearch::new(self, from, |loc| loc == to,
+                                     |loc| self.get_tile(loc).terrain.move_cost())
+            .toward(to)
+            .movement(movement);
+        astar::astar(search).map(|path| path.into_iter().collect())
     }
-    fn calc_min_y(components: &[(Location, Terrain)]) -> i32 {
-        components.iter().map(|c| c.0.y).min().unwrap()
+    // Like find_path, but if the destination can't be reached, returns the
+    // path to the reachable tile that looked closest to it instead, so a
+    // monster can still close in on a player it can't get to.
+    pub fn find_path_or_closest(&self, from: Location, to: Location) -> Vec<Location> {
+        let closest = Cell::new((i32::max_value(), from));
+        let search = GridSearch::new(self, from, |loc| loc == to,
+                                     |loc| self.get_tile(loc).terrain.move_cost())
+            .toward(to);
+        if let Some(path) = astar::astar(TrackClosest { search: search, closest: &closest }) {
+            return path.into_iter().collect();
+        }
+
+        let (_, best) = closest.get();
+        self.find_path(from, best).unwrap_or(vec![from])
     }
-    fn calc_max_y(components: &[(Location, Terrain)]) -> i32 {
-        components.iter().map(|c| c.0.y).max().unwrap()
+    // Like find_path, but gives up and returns None after expanding
+    // max_nodes tiles, so a hopeless search can't stall a turn.
+    pub fn find_path_bounded(&self, from: Location, to: Location,
+                             max_nodes: usize) -> Option<Vec<Location>> {
+        let budget = Cell::new(max_nodes);
+        let search = GridSearch::new(self, from, |loc| loc == to,
+                                     |loc| self.get_tile(loc).terrain.move_cost())
+            .toward(to);
+        astar::astar(LimitExpansions { search: search, budget: &budget })
+            .map(|path| path.into_iter().collect())
     }
-}
+    // Tiles a unit at `from` could move to with `budget` movement points,
+    // and what each costs to reach, stepping orthogonally and paying each
+    // terrain's move cost. Tiles with a blocking entity on them are never
+    // destinations, though with pass_occupied the unit can go through them.
+    // The unit's own tile costs nothing.
+    pub fn reachable(&self, from: Location, budget: i32,
+                     pass_occupied: bool) -> HashMap<Location, i32> {
+        let mut costs = HashMap::new();
+        if !self.in_bounds(from) || budget < 0 { return costs; }
+        costs.insert(from, 0);
+        let mut queue = BinaryHeap::new();
+        queue.push((0, self.index_of(from)));
 
-// Trait to extend iterators to provide a random function.
-trait IterRandomExt<T> {
-    fn random<R: Rng>(&mut self, rng: &mut R) -> T;
+        // The queue holds negated costs so the cheapest tile pops first.
+        while let Some((neg_cost, index)) = queue.pop() {
+            let cost = -neg_cost;
+            let loc = self.location_of(index);
+            if costs.get(&loc).map_or(false, |&best| best < cost) { continue; }
+            for next in loc.orthogonal().filter(|next| self.in_bounds(*next)) {
+                if !self.get_tile(next).terrain.can_enter(next - loc) { continue; }
+                let step = match self.get_tile(next).terrain.move_cost() {
+                    Some(step) => step,
+                    None => continue
+                };
+                if !pass_occupied && !self.is_passable(next) { continue; }
+                let next_cost = cost + step;
+                if next_cost > budget { continue; }
+                if costs.get(&next).map_or(true, |&best| next_cost < best) {
+                    costs.insert(next, next_cost);
+                    queue.push((-next_cost, self.index_of(next)));
+                }
+            }
+        }
+        costs.retain(|&loc, _| loc == from || self.is_passable(loc));
+
+        costs
+    }
+    // Walking distance from the nearest source to every tile, indexed like
+    // the tiles themselves; None for tiles no source can reach. Monsters can
+    // head for a source by stepping to whichever neighbor is closest.
+    pub fn dijkstra_map(&self, sources: &[Location]) -> Vec<Option<i32>> {
+        let mut distances: Vec<Option<i32>> = vec![None; self.tiles.len()];
+        let mut queue = BinaryHeap::new();
+        for &source in sources.iter().filter(|loc| self.in_bounds(**loc)) {
+            let index = self.index_of(source);
+            distances[index] = Some(0);
+            queue.push((0, index));
+        }
+
+        // The queue holds negated distances so the closest tile pops first.
+        while let Some((neg_distance, index)) = queue.pop() {
+            let distance = -neg_distance;
+            if let Some(best) = distances[index] {
+                if best < distance { continue; }
+            }
+            let loc = self.location_of(index);
+            for next in loc.orthogonal().filter(|next| self.in_bounds(*next)) {
+                // Distances run backwards, from next onto loc.
+                if !self.get_tile(loc).terrain.can_enter(loc - next) { continue; }
+                let step = match self.get_tile(next).terrain.move_cost() {
+                    Some(step) => step,
+                    None => continue
+                };
+                let next_index = self.index_of(next);
+                let next_distance = distance + step;
+                let shorter = match distances[next_index] {
+                    Some(best) => next_distance < best,
+                    None => true
+                };
+                if shorter {
+                    distances[next_index] = Some(next_distance);
+                    queue.push((-next_distance, next_index));
+                }
+            }
+        }
+
+        distances
+    }
+    // Closest tile matching the predicate that can be walked to from the
+    // given location, counting steps rather than movement cost; the
+    // starting tile itself is checked first.
+    pub fn nearest<F>(&self, from: Location, pred: F) -> Option<Location>
+        where F: Fn(&Tile) -> bool {
+        self.nearest_where(from, |tile, _| pred(tile))
+    }
+    // Path to the closest walkable tile that hasn't been explored yet; None
+    // once everything reachable has been.
+    pub fn autoexplore_path(&self, from: Location,
+                            explored: &HashSet<Location>) -> Option<Vec<Location>> {
+        self.nearest_where(from, |tile, loc| tile.terrain.is_walkable() && !explored.contains(&loc))
+            .and_then(|target| self.find_path(from, target))
+    }
+    fn nearest_where<F>(&self, from: Location, pred: F) -> Option<Location>
+        where F: Fn(&Tile, Location) -> bool {
+        if !self.in_bounds(from) { return None; }
+        let mut seen = HashSet::new();
+        let mut queue = VecDeque::new();
+        seen.insert(from);
+        queue.push_back(from);
+        while let Some(loc) = queue.pop_front() {
+            if pred(self.get_tile(loc), loc) { return Some(loc); }
+            let neighbors = NeighborIterator::new(self, loc, Movement::Orthogonal,
+                                                  |loc| self.get_tile(loc).terrain.move_cost());
+            for (next, _) in neighbors {
+                if seen.insert(next) {
+                    queue.push_back(next);
+                }
+            }
+        }
+
+        None
+    }
+    // Groups the walkable tiles into 4-connected regions, largest first.
+    pub fn connected_regions(&self) -> Vec<Vec<Location>> {
+        let mut seen = HashSet::new();
+        let mut regions = Vec::new();
+        for (tile, loc) in self.tiles() {
+            if !tile.terrain.is_walkable() || seen.contains(&loc) { continue; }
+            let region = self.flood(loc, |loc| self.get_tile(loc).terrain.is_walkable());
+            seen.extend(region.iter().cloned());
+            regions.push(region);
+        }
+        regions.sort_by(|a, b| b.len().cmp(&a.len()));
+
+        regions
+    }
+    // Pairs of rooms, by index into rooms(), that a corridor runs between,
+    // lowest index first and sorted. A corridor is any stretch of walkable
+    // tiles outside every room; rooms it reaches are all joined to each
+    // other.
+    pub fn room_graph(&self) -> Vec<(usize, usize)> {
+        let in_room = |loc: Location| self.rooms.iter().any(|room| room.contains(loc));
+        let corridor = |loc: Location| self.get_tile(loc).terrain.is_walkable() && !in_room(loc);
+        let mut seen = HashSet::new();
+        let mut edges = Vec::new();
+        for (_, loc) in self.tiles().filter(|&(_, loc)| corridor(loc)) {
+            if seen.contains(&loc) { continue; }
+            let region = self.flood(loc, &corridor);
+            let mut touched: Vec<_> = region.iter()
+                .flat_map(|loc| loc.orthogonal())
+                .filter(|next| self.in_bounds(*next))
+                .flat_map(|next| self.rooms.iter().enumerate()
+                    .filter(move |&(_, room)| room.contains(next))
+                    .map(|(i, _)| i))
+                .collect();
+            touched.sort();
+            touched.dedup();
+            for (n, &i) in touched.iter().enumerate() {
+                edges.extend(touched[n + 1..].iter().map(|&j| (i, j)));
+            }
+            seen.extend(region);
+        }
+        edges.sort();
+        edges.dedup();
+
+        edges
+    }
+    // Clears away walkable tiles that can't be reached from the given
+    // location, along with walls left bordering only what was cleared,
+    // and forgets rooms with nothing reachable left in them.
+    pub fn prune_unreachable(&mut self, from: Location) {
+        let reached: HashSet<_> = if self.in_bounds(from) {
+            self.flood(from, |loc| self.get_tile(loc).terrain.is_walkable()).into_iter().collect()
+        } else { HashSet::new() };
+        let cleared: HashSet<_> = self.tiles()
+            .filter(|&(tile, loc)| tile.terrain.is_walkable() && !reached.contains(&loc))
+            .map(|(_, loc)| loc)
+            .collect();
+        let stranded: Vec<_> = self.tiles()
+            .filter(|&(tile, _)| tile.terrain == Terrain::Wall)
+            .map(|(_, loc)| loc)
+            .filter(|loc| {
+                let neighbors = self.neighbors(*loc, true);
+                neighbors.iter().any(|next| cleared.contains(next)) &&
+                    !neighbors.iter().any(|next| reached.contains(next))
+            })
+            .collect();
+        for loc in cleared.iter().chain(stranded.iter()) {
+            self.get_tile_mut(*loc).terrain = Terrain::Nothing;
+        }
+        self.rooms.retain(|room| reached.iter().any(|loc| room.contains(*loc)));
+    }
+    // Walls up corridors that lead nowhere. Each pass turns every floor or
+    // door tile with exactly one walkable orthogonal neighbor back into
+    // wall, so max_length passes trim spurs up to that many tiles long.
+    // Only corridors are trimmed, as in room_graph: tiles inside a room, or
+    // with entities on them, are left alone.
+    pub fn remove_dead_ends(&mut self, max_length: usize) {
+        for _ in 0..max_length {
+            let dead_ends: Vec<_> = self.tiles()
+                .filter(|&(tile, _)| {
+                    (tile.terrain == Terrain::Floor || tile.terrain == Terrain::Door) &&
+                        tile.entities.is_empty()
+                })
+                .map(|(_, loc)| loc)
+                .filter(|loc| !self.rooms.iter().any(|room| room.contains(*loc)))
+                .filter(|loc| {
+                    loc.orthogonal()
+                        .filter(|next| self.terrain_at(*next).map_or(false, |t| t.is_walkable()))
+                        .count() == 1
+                })
+                .collect();
+            if dead_ends.is_empty() { break; }
+            for loc in dead_ends {
+                self.get_tile_mut(loc).terrain = Terrain::Wall;
+            }
+        }
+    }
+    // Checks that the map holds together: there's a tile for every
+    // location, each entity is on exactly one tile, and the recorded entity
+    // locations agree with the tiles. Every broken invariant is reported.
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors = Vec::new();
+        let expected = self.width as usize * self.height as usize;
+        if self.tiles.len() != expected {
+            errors.push(format!("A {}x{} map has {} tiles rather than {}.",
+                                self.width, self.height, self.tiles.len(), expected));
+        }
+
+        let mut seen = HashMap::new();
+        for (index, tile) in self.tiles.iter().enumerate() {
+            let loc = location_from_index(index, self.width);
+            for entity in tile.entities.iter() {
+                if let Some(other) = seen.insert(entity.id, loc) {
+                    errors.push(format!("Entity {} is at both {:?} and {:?}.",
+                                        entity.id, other, loc));
+                }
+                if entity.id >= self.next_entity_id {
+                    errors.push(format!("Entity {} at {:?} was never spawned.", entity.id, loc));
+                }
+                match self.entity_locations.get(&entity.id) {
+                    Some(&recorded) if recorded == loc => {},
+                    Some(&recorded) => {
+                        errors.push(format!("Entity {} is at {:?} but recorded at {:?}.",
+                                            entity.id, loc, recorded));
+                    },
+                    None => {
+                        errors.push(format!("Entity {} at {:?} isn't recorded.", entity.id, loc));
+                    }
+                }
+            }
+        }
+        let mut missing: Vec<_> = self.entity_locations.iter()
+            .filter(|&(id, _)| !seen.contains_key(id))
+            .collect();
+        missing.sort_by_key(|&(id, _)| *id);
+        for (id, loc) in missing {
+            errors.push(format!("Entity {} is recorded at {:?} but isn't there.", id, loc));
+        }
+
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
+    }
+    // Like validate, but also requires every walkable tile to be reachable
+    // from every other.
+    pub fn validate_connected(&self) -> Result<(), Vec<String>> {
+        let mut errors = match self.validate() {
+            Ok(()) => Vec::new(),
+            Err(errors) => errors
+        };
+        let regions = self.connected_regions().len();
+        if regions > 1 {
+            errors.push(format!("The map is split into {} walkable regions.", regions));
+        }
+
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
+    }
+    // Paints over start's terrain, and that of every tile 4-connected to it
+    // with the same terrain, like a paint bucket. Does nothing if start is
+    // outside the map or already has the new terrain.
+    pub fn flood_fill(&mut self, start: Location, new_terrain: Terrain) {
+        let old_terrain = match self.terrain_at(start) {
+            Some(terrain) if terrain != new_terrain => terrain,
+            _ => return
+        };
+        for loc in self.flood(start, |loc| self.get_tile(loc).terrain == old_terrain) {
+            self.get_tile_mut(loc).terrain = new_terrain;
+        }
+    }
+    // Locations 4-connected to start through tiles matching the predicate,
+    // in the order they were reached.
+    fn flood<F>(&self, start: Location, matches: F) -> Vec<Location>
+        where F: Fn(Location) -> bool {
+        let mut seen = HashSet::new();
+        let mut queue = VecDeque::new();
+        let mut region = Vec::new();
+        seen.insert(start);
+        queue.push_back(start);
+        while let Some(loc) = queue.pop_front() {
+            region.push(loc);
+            for next in loc.orthogonal().filter(|next| self.in_bounds(*next)) {
+                if matches(next) && seen.insert(next) {
+                    queue.push_back(next);
+                }
+            }
+        }
+
+        region
+    }
+    // In-bounds neighbors of a location: the four orthogonal ones, followed
+    // by the four diagonal ones if asked for.
+    pub fn neighbors(&self, loc: Location, diagonal: bool) -> Vec<Location> {
+        let diagonals: &[Offset] = if diagonal { &DIAGONAL } else { &[] };
+        ORTHOGONAL.iter().chain(diagonals.iter())
+            .map(|&offset| loc + offset)
+            .filter(|adjacent| self.in_bounds(*adjacent))
+            .collect()
+    }
 }
 
-impl<I> IterRandomExt<I::Item> for I where I: Iterator, I::Item: Clone {
-    fn random<R: Rng>(&mut self, rng: &mut R) -> I::Item {
-        let elements: Vec<_> = self.collect();
-        assert!(elements.len() > 0);
-        let random = rng.gen_range::<usize>(0, elements.len());
-        elements[random].clone()
+// Shows the size and then the map as to_ascii draws it.
+impl ::std::fmt::Debug for WorldMap {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) ->
+        Result<(), ::std::fmt::Error> {
+        f.write_fmt(format_args!("WorldMap {}x{}\n{}", self.width, self.height, self.to_ascii()))
     }
 }
 
-// Iterates through neighbors; used for A* algorithm.
-struct NeighborIterator {
-    adjacent: Vec<Location>,
-    current: usize
+#[derive(Copy, Clone, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Location {
+    pub x: i32,
+    pub y: i32
 }
 
-impl NeighborIterator {
-    pub fn new(world: &WorldMap, loc: Location) -> Self {
-        let adjacent = world.get_adjacent(loc).iter()
-            .map(|x| *x)
-            .filter(|loc| world.get_tile(*loc).terrain == Terrain::Nothing)
-            .collect();
+impl Location {
+    pub fn new(x: i32, y: i32) -> Self {
+        Location {x: x, y: y}
+    }
+    pub fn manhattan(&self, other: &Location) -> i32 {
+        let total_x = if self.x > other.x { self.x - other.x } else { other.x - self.x };
+        let total_y = if self.y > other.y { self.y - other.y } else { other.y - self.y };
+
+        total_x + total_y
+    }
+    // Number of king's moves between two locations; the admissible
+    // heuristic for eight-way movement.
+    pub fn chebyshev(&self, other: &Location) -> i32 {
+        let total_x = (self.x - other.x).abs();
+        let total_y = (self.y - other.y).abs();
+
+        if total_x > total_y { total_x } else { total_y }
+    }
+    pub fn euclidean_squared(&self, other: &Location) -> i32 {
+        let total_x = self.x - other.x;
+        let total_y = self.y - other.y;
 
-        NeighborIterator { adjacent: adjacent, current: 0 }
+        total_x * total_x + total_y * total_y
+    }
+    // The four orthogonal neighbors: left, up, right, down. They aren't
+    // checked against any map's bounds.
+    pub fn orthogonal(&self) -> OrthogonalIterator {
+        OrthogonalIterator { center: *self, current: 0 }
     }
 }
 
-impl Iterator for NeighborIterator {
-    type Item = (Location, i32);
+// Iterates through the four orthogonal neighbors of a location, in the same
+// order as ORTHOGONAL.
+pub struct OrthogonalIterator {
+    center: Location,
+    current: usize
+}
 
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.current < self.adjacent.len() {
+impl Iterator for OrthogonalIterator {
+    type Item = Location;
+
+    fn next(&mut self) -> Option<Location> {
+        if self.current < ORTHOGONAL.len() {
             self.current += 1;
-            Some((self.adjacent[self.current - 1], 1))
+            Some(self.center + ORTHOGONAL[self.current - 1])
         } else {
             None
         }
     }
 }
 
-// Search problem for connecting rooms with A* algorithm.
-struct ConnectRooms<'a> {
-    world: &'a WorldMap,
-    start: Location,
-    end: Location
-}
+impl ::std::ops::Add<Offset> for Location {
+    type Output = Location;
 
-impl<'a> ConnectRooms<'a> {
-    pub fn new(world: &'a WorldMap, start: Location, end: Location) -> Self {
-        ConnectRooms { world: world, start: start, end: end }
+    fn add(self, offset: Offset) -> Location {
+        Location::new(self.x + offset.dx, self.y + offset.dy)
     }
 }
 
-impl<'a> astar::SearchProblem<Location, i32, NeighborIterator> for ConnectRooms<'a> {
-    fn start(&self) -> Location {
-        self.start
+impl ::std::ops::Sub<Offset> for Location {
+    type Output = Location;
+
+    fn sub(self, offset: Offset) -> Location {
+        Location::new(self.x - offset.dx, self.y - offset.dy)
     }
-    fn is_end(&self, loc: &Location) -> bool {
-        *loc == self.end
+}
+
+impl ::std::ops::Sub<Location> for Location {
+    type Output = Offset;
+
+    fn sub(self, other: Location) -> Offset {
+        Offset::new(self.x - other.x, self.y - other.y)
     }
-    fn heuristic(&self, loc: &Location) -> i32 {
-        loc.manhattan(&self.end)
+}
+
+impl ::std::fmt::Debug for Location {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) ->
+        Result<(), ::std::fmt::Error> {
+        f.write_fmt(format_args!("({}, {})", self.x, self.y))
     }
-    fn neighbors(&self, at: &Location) -> NeighborIterator {
-        NeighborIterator::new(&self.world, *at)
+}
+
+// Displacement between two locations.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Offset {
+    pub dx: i32,
+    pub dy: i32
+}
+
+impl Offset {
+    pub fn new(dx: i32, dy: i32) -> Self {
+        Offset {dx: dx, dy: dy}
     }
 }
 
-#[test]
-fn test_feature_size() {
-    let feature = Feature::new(vec![
-        (Location::new(0, 0), Terrain::Wall),
-        (Location::new(1, 0), Terrain::Wall),
-        (Location::new(1, 1), Terrain::Wall)]);
-    assert_eq!(feature.width(), 2);
-    assert_eq!(feature.height(), 2);
+// One of the four compass directions; north is up the map.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Direction {
+    North,
+    East,
+    South,
+    West
 }
 
-#[test]
-fn test_build_feature() {
-    // Set up some feature shape:
-    //
-    // ....
-    // .##.
-    // .##.
-    // .#..
-    // ....
-    let comps = vec![
-        (Location::new(1, 1), Terrain::Wall),
-        (Location::new(2, 1), Terrain::Wall),
-        (Location::new(1, 2), Terrain::Wall),
-        (Location::new(2, 2), Terrain::Wall),
-        (Location::new(1, 3), Terrain::Wall)
-    ];
+impl Direction {
+    // A single step in this direction.
+    pub fn offset(&self) -> Offset {
+        match *self {
+            Direction::North => Offset::new(0, -1),
+            Direction::East => Offset::new(1, 0),
+            Direction::South => Offset::new(0, 1),
+            Direction::West => Offset::new(-1, 0)
+        }
+    }
+    // The direction a quarter turn clockwise from this one.
+    pub fn clockwise(&self) -> Direction {
+        match *self {
+            Direction::North => Direction::East,
+            Direction::East => Direction::South,
+            Direction::South => Direction::West,
+            Direction::West => Direction::North
+        }
+    }
+}
 
-    // Place top left at (2,3).
-    //
+// Offsets to the four orthogonal neighbors: left, up, right, down.
+const ORTHOGONAL: [Offset; 4] = [
+    Offset {dx: -1, dy: 0},
+    Offset {dx: 0, dy: -1},
+    Offset {dx: 1, dy: 0},
+    Offset {dx: 0, dy: 1}
+];
+
+// Offsets to the four diagonal neighbors.
+const DIAGONAL: [Offset; 4] = [
+    Offset {dx: -1, dy: -1},
+    Offset {dx: 1, dy: -1},
+    Offset {dx: -1, dy: 1},
+    Offset {dx: 1, dy: 1}
+];
+
+// Path costs of a single step; diagonals are roughly sqrt(2) times longer.
+const ORTHOGONAL_COST: i32 = 10;
+const DIAGONAL_COST: i32 = 14;
+
+// How many steps a drunken corridor may take per tile between its ends.
+const DRUNKEN_STEPS_PER_TILE: i32 = 20;
+
+// Which neighbors a path may step to.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Movement {
+    // The four orthogonal neighbors.
+    Orthogonal,
+    // All eight neighbors, without cutting between two blocked tiles.
+    Diagonal
+}
+
+// Ways of measuring how far apart two locations are.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Metric {
+    // Orthogonal steps; balls are diamonds.
+    Manhattan,
+    // King's moves; balls are squares.
+    Chebyshev,
+    // Straight-line distance; balls are discs.
+    Euclidean
+}
+
+impl Metric {
+    fn within(&self, a: &Location, b: &Location, radius: i32) -> bool {
+        match *self {
+            Metric::Manhattan => a.manhattan(b) <= radius,
+            Metric::Chebyshev => a.chebyshev(b) <= radius,
+            Metric::Euclidean => a.euclidean_squared(b) <= radius * radius
+        }
+    }
+}
+
+impl Movement {
+    // Cost of the cheapest unobstructed path between two locations; used
+    // as the A* heuristic.
+    fn distance(&self, a: &Location, b: &Location) -> i32 {
+        match *self {
+            Movement::Orthogonal => ORTHOGONAL_COST * a.manhattan(b),
+            Movement::Diagonal => {
+                let dx = (a.x - b.x).abs();
+                let dy = (a.y - b.y).abs();
+                let (short, long) = if dx < dy { (dx, dy) } else { (dy, dx) };
+                DIAGONAL_COST * short + ORTHOGONAL_COST * (long - short)
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Terrain {
+    Debug,
+    Nothing,
+    Floor,
+    Wall,
+    Door,
+    Rubble,
+    StairsUp,
+    StairsDown,
+    Grass,
+    // Shallow enough to wade through, slowly.
+    Water,
+    // Can be seen across but not walked over.
+    Chasm,
+    // Can be walked through but not seen through.
+    Fog,
+    // A drop that can only be stepped onto while heading the given way.
+    Ledge(Direction)
+}
+
+impl Terrain {
+    // Whether something can stand on this terrain.
+    pub fn is_walkable(&self) -> bool {
+        match *self {
+            Terrain::Floor | Terrain::Door | Terrain::Debug | Terrain::Rubble |
+            Terrain::StairsUp | Terrain::StairsDown | Terrain::Grass | Terrain::Water |
+            Terrain::Fog | Terrain::Ledge(_) => true,
+            Terrain::Wall | Terrain::Nothing | Terrain::Chasm => false
+        }
+    }
+    // Whether this terrain can be seen through.
+    pub fn is_transparent(&self) -> bool {
+        match *self {
+            Terrain::Floor | Terrain::Debug | Terrain::Rubble |
+            Terrain::StairsUp | Terrain::StairsDown | Terrain::Grass | Terrain::Water |
+            Terrain::Chasm | Terrain::Ledge(_) => true,
+            Terrain::Wall | Terrain::Door | Terrain::Nothing | Terrain::Fog => false
+        }
+    }
+    // Symbol used for the terrain in text maps.
+    pub fn to_char(&self) -> char {
+        match *self {
+            Terrain::Wall => '#',
+            Terrain::Floor => '.',
+            Terrain::Nothing => ' ',
+            Terrain::Door => '+',
+            Terrain::Rubble => ':',
+            Terrain::StairsUp => '<',
+            Terrain::StairsDown => '>',
+            Terrain::Grass => '"',
+            Terrain::Water => '~',
+            Terrain::Chasm => '_',
+            Terrain::Fog => '%',
+            Terrain::Ledge(Direction::North) => '^',
+            Terrain::Ledge(Direction::East) => '}',
+            Terrain::Ledge(Direction::South) => 'v',
+            Terrain::Ledge(Direction::West) => '{',
+            Terrain::Debug => '?'
+        }
+    }
+    // The terrain a text map symbol stands for, if any.
+    pub fn from_char(c: char) -> Option<Terrain> {
+        match c {
+            '#' => Some(Terrain::Wall),
+            '.' => Some(Terrain::Floor),
+            ' ' => Some(Terrain::Nothing),
+            '+' => Some(Terrain::Door),
+            ':' => Some(Terrain::Rubble),
+            '<' => Some(Terrain::StairsUp),
+            '>' => Some(Terrain::StairsDown),
+            '"' => Some(Terrain::Grass),
+            '~' => Some(Terrain::Water),
+            '_' => Some(Terrain::Chasm),
+            '%' => Some(Terrain::Fog),
+            '^' => Some(Terrain::Ledge(Direction::North)),
+            '}' => Some(Terrain::Ledge(Direction::East)),
+            'v' => Some(Terrain::Ledge(Direction::South)),
+            '{' => Some(Terrain::Ledge(Direction::West)),
+            '?' => Some(Terrain::Debug),
+            _ => None
+        }
+    }
+    // How many times more expensive than plain floor it is to step onto
+    // this terrain; None if it can't be walked on at all.
+    pub fn move_cost(&self) -> Option<i32> {
+        match *self {
+            Terrain::Floor | Terrain::Door | Terrain::Debug |
+            Terrain::StairsUp | Terrain::StairsDown | Terrain::Grass | Terrain::Fog |
+            Terrain::Ledge(_) => Some(1),
+            Terrain::Rubble => Some(3),
+            Terrain::Water => Some(4),
+            Terrain::Wall | Terrain::Nothing | Terrain::Chasm => None
+        }
+    }
+    // Whether a step by `step` may end on this terrain. Ledges can only be
+    // dropped onto while heading their way, diagonally included; everything
+    // else can be entered from any side.
+    pub fn can_enter(&self, step: Offset) -> bool {
+        match *self {
+            Terrain::Ledge(direction) => {
+                let along = direction.offset();
+                step.dx * along.dx + step.dy * along.dy > 0
+            },
+            _ => true
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Entity {
+    id: u64,
+    blocks: bool
+}
+
+impl Entity {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+    // Whether other blocking entities are kept off this one's tile.
+    pub fn blocks(&self) -> bool {
+        self.blocks
+    }
+}
+
+// Reasons WorldMap::move_entity can fail.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum MoveError {
+    // The entity isn't on the source tile.
+    NotFound,
+    // The destination is outside the map.
+    OutOfBounds,
+    // The destination can't be walked on.
+    NotWalkable,
+    // The destination is a ledge that can't be entered from this side.
+    WrongWay,
+    // A blocking entity is moving onto a tile with another blocking entity.
+    Blocked
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Tile {
+    pub terrain: Terrain,
+    pub entities: Vec<Entity>,
+    visibility: Visibility
+}
+
+impl Tile {
+    pub fn new(terrain: Terrain) -> Self {
+        Tile {terrain: terrain, entities: Vec::new(), visibility: Visibility::Unseen}
+    }
+    pub fn visibility(&self) -> Visibility {
+        self.visibility
+    }
+    pub fn set_visibility(&mut self, visibility: Visibility) {
+        self.visibility = visibility;
+    }
+}
+
+// What the player knows about a tile, for fog of war.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Visibility {
+    // Never been in view.
+    Unseen,
+    // Seen before, but not in view now.
+    Remembered,
+    // In view now.
+    Visible
+}
+
+pub struct TileIterator<'a> {
+    tiles: &'a [Tile],
+    width: i32,
+    curr: usize
+}
+
+impl<'a> TileIterator<'a> {
+    pub fn new(tiles: &'a [Tile], width: i32) -> Self {
+        TileIterator {tiles: tiles, width: width, curr: 0}
+    }
+}
+
+impl<'a> Iterator for TileIterator<'a> {
+    type Item = (&'a Tile, Location);
+
+    fn next(&mut self) -> Option<(&'a Tile, Location)> {
+        if self.curr < self.tiles.len() {
+            let this = self.curr;
+            self.curr += 1;
+            Some((&self.tiles[this], location_from_index(this, self.width)))
+        } else { None }
+    }
+}
+
+// Converts a row-major tile index back into a location.
+// The coordinates are worked out before narrowing them to i32, so indices
+// too big for an i32 still come out right.
+fn location_from_index(index: usize, width: i32) -> Location {
+    let width = width as usize;
+    Location::new((index % width) as i32, (index / width) as i32)
+}
+
+// GENERATION STUFF.
+// A feature in the world, consisting of some arrangement of terrain.
+// Features consist of relative coordinates; they can be placed at any
+// arbitrary location.
+#[derive(Clone, Debug)]
+pub struct Feature {
+    components: Vec<(Location, Terrain)>
+}
+
+impl Feature {
+    pub fn new(components: Vec<(Location, Terrain)>) -> Self {
+        Feature { components: components }
+    }
+    // Whether a solid (non-Nothing) tile of this feature lies within
+    // `padding` tiles of a solid tile of the other one. With no padding,
+    // features only overlap if they share a tile; touching is fine.
+    pub fn overlaps(&self, other: &Feature, padding: i32) -> bool {
+        // Bounding boxes too far apart can't overlap.
+        if !self.bounds().inflate(padding).intersects(&other.bounds()) {
+            return false;
+        }
+
+        let solid: HashSet<_> = other.components.iter()
+            .filter(|c| c.1 != Terrain::Nothing)
+            .map(|c| c.0)
+            .collect();
+        for &(loc, terrain) in self.components.iter() {
+            if terrain == Terrain::Nothing { continue; }
+            for dx in -padding..padding + 1 {
+                for dy in -padding..padding + 1 {
+                    if solid.contains(&(loc + Offset::new(dx, dy))) { return true; }
+                }
+            }
+        }
+
+        return false;
+    }
+    // Like overlaps with no padding, except that a wall may sit on one of
+    // the other feature's walls.
+    pub fn overlaps_sharing_walls(&self, other: &Feature) -> bool {
+        if !self.bounds().intersects(&other.bounds()) {
+            return false;
+        }
+
+        let solid: HashMap<_, _> = other.components.iter()
+            .filter(|c| c.1 != Terrain::Nothing)
+            .cloned()
+            .collect();
+        self.components.iter().any(|&(loc, terrain)| match solid.get(&loc) {
+            Some(&other) => terrain != Terrain::Nothing &&
+                !(terrain == Terrain::Wall && other == Terrain::Wall),
+            None => false
+        })
+    }
+    // Smallest rectangle holding every component, empty ones included.
+    pub fn bounds(&self) -> Rect {
+        Rect::bounding(self.components.iter().map(|c| &c.0))
+    }
+    pub fn width(&self) -> i32 {
+        self.bounds().width
+    }
+    pub fn height(&self) -> i32 {
+        self.bounds().height
+    }
+    // Center of the feature's bounding box.
+    pub fn center(&self) -> Location {
+        self.bounds().center()
+    }
+    pub fn iter(&self) -> ::std::slice::Iter<(Location, Terrain)> {
+        self.components.iter()
+    }
+    pub fn walls<'a>(&'a self) -> Box<Iterator<Item=&'a Location> + 'a> {
+        Box::new(self.components.iter()
+            .filter(|c| c.1 == Terrain::Wall)
+            .map(|c| &c.0))
+    }
+    pub fn floors<'a>(&'a self) -> Box<Iterator<Item=&'a Location> + 'a> {
+        Box::new(self.components.iter()
+            .filter(|c| c.1 == Terrain::Floor)
+            .map(|c| &c.0))
+    }
+    // Panics if the feature has no floor.
+    pub fn random_floor<R: Rng>(&self, rng: &mut R) -> Location {
+        *self.floors().random(rng)
+    }
+    // Walls bordering one of the feature's floors; these can be dug out
+    // to make an entrance.
+    pub fn doors(&self) -> Vec<Location> {
+        let floors: HashSet<_> = self.floors().cloned().collect();
+        self.walls()
+            .filter(|w| ORTHOGONAL.iter().any(|&offset| floors.contains(&(**w + offset))))
+            .cloned()
+            .collect()
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum HorizontalAlignment {
+    Left,
+    Center,
+    Right
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum VerticalAlignment {
+    Top,
+    Center,
+    Bottom
+}
+
+// Builds a map by running generation steps in the order they're added,
+// each working on what the steps before it left behind:
+//
+//     WorldMapBuilder::new(80, 50)
+//         .add_rooms(12)
+//         .connect_rooms(CorridorStyle::Drunken)
+//         .place_stairs()
+//         .wrap_walls()
+//         .build(&mut rng)
+//
+// Settings the steps don't take directly, like room sizes, come from the
+// config.
+#[derive(Clone, Debug)]
+pub struct WorldMapBuilder {
+    width: i32,
+    height: i32,
+    config: WorldMapConfig,
+    steps: Vec<BuildStep>,
+    // Paint corridors with Terrain::Debug instead of floor and doors.
+    debug: bool
+}
+
+#[derive(Copy, Clone, Debug)]
+enum BuildStep {
+    AddRooms(usize),
+    ConnectRooms(CorridorStyle),
+    PlaceStairs,
+    WrapWalls,
+    VaryFloors
+}
+
+impl WorldMapBuilder {
+    pub fn new(width: i32, height: i32) -> Self {
+        WorldMapBuilder {
+            width: width,
+            height: height,
+            config: WorldMapConfig::default(),
+            steps: Vec::new(),
+            debug: false
+        }
+    }
+    // The steps WorldMap::generate_with_config runs.
+    pub fn preset(width: i32, height: i32, config: &WorldMapConfig) -> Self {
+        WorldMapBuilder::new(width, height)
+            .config(config.clone())
+            .add_rooms(config.max_rooms)
+            .connect_rooms(config.corridor_style)
+            .vary_floors()
+    }
+    pub fn config(mut self, config: WorldMapConfig) -> Self {
+        self.config = config;
+        self
+    }
+    // Places up to `count` more rooms that don't overlap the others.
+    pub fn add_rooms(mut self, count: usize) -> Self {
+        self.steps.push(BuildStep::AddRooms(count));
+        self
+    }
+    // Joins every room placed so far, undrawing any that can't be reached.
+    pub fn connect_rooms(mut self, style: CorridorStyle) -> Self {
+        self.steps.push(BuildStep::ConnectRooms(style));
+        self
+    }
+    // Puts up stairs in one room and down stairs in another. The map then
+    // starts on the up stairs.
+    pub fn place_stairs(mut self) -> Self {
+        self.steps.push(BuildStep::PlaceStairs);
+        self
+    }
+    pub fn wrap_walls(mut self) -> Self {
+        self.steps.push(BuildStep::WrapWalls);
+        self
+    }
+    // Swaps plain floor for the config's floor_variety terrains. Does
+    // nothing if none are given.
+    pub fn vary_floors(mut self) -> Self {
+        self.steps.push(BuildStep::VaryFloors);
+        self
+    }
+    // Runs the steps, returning the map and where to start on it: the up
+    // stairs if there are any, or else a random floor in a random room.
+    pub fn build<R: Rng>(&self, rng: &mut R) -> Result<(WorldMap, Location), GenError> {
+        let (mut world, features, stairs) = self.run(rng)?;
+        let start = match stairs {
+            Some((up, _)) => up,
+            None if features.len() > 0 => features.iter().random(rng).random_floor(rng),
+            None => return Err(GenError::TooFewRooms)
+        };
+        if self.config.prune_unreachable {
+            world.prune_unreachable(start);
+        }
+
+        Ok((world, start))
+    }
+    // Runs the steps, returning the map, its rooms and its stairs.
+    fn run<R: Rng>(&self, rng: &mut R)
+                   -> Result<(WorldMap, Vec<Feature>, Option<(Location, Location)>), GenError> {
+        let (width, height, config) = (self.width, self.height, &self.config);
+        if width <= 0 || height <= 0 { return Err(GenError::InvalidDimensions); }
+        if !config.is_valid() { return Err(GenError::InvalidConfig); }
+        if width < config.smallest_room_size() || height < config.smallest_room_size() {
+            return Err(GenError::MapTooSmall);
+        }
+
+        let mut world = WorldMap::new(width, height);
+        let mut features = Vec::new();
+        let mut stairs = None;
+        for step in self.steps.iter() {
+            match *step {
+                BuildStep::AddRooms(count) => {
+                    world.add_features(rng, &mut features, config, count);
+                },
+                BuildStep::ConnectRooms(style) => {
+                    let config = WorldMapConfig { corridor_style: style, .. config.clone() };
+                    features = world.connect_all(rng, features, &config, self.debug);
+                },
+                BuildStep::PlaceStairs => {
+                    stairs = Some(world.place_stairs(rng, &features)?);
+                },
+                BuildStep::WrapWalls => world.wrap_walls(),
+                BuildStep::VaryFloors => world.vary_floors(rng, &config.floor_variety)
+            }
+        }
+        if features.len() < config.min_rooms { return Err(GenError::TooFewRooms); }
+        world.rooms = features.iter().map(|feature| feature.bounds()).collect();
+
+        Ok((world, features, stairs))
+    }
+}
+
+// Build features! Take the raw feature shape and translate it
+// according to the given alignment and absolute location.
+#[derive(Clone, Debug)]
+pub struct FeatureBuilder {
+    components: Vec<(Location, Terrain)>,
+    location: Location,
+    horiz_align: HorizontalAlignment,
+    vert_align: VerticalAlignment
+}
+
+impl FeatureBuilder {
+    pub fn new(components: Vec<(Location, Terrain)>) -> Self {
+        assert!(components.len() > 0);
+        FeatureBuilder {
+            components: components,
+            location: Location::new(0, 0),
+            horiz_align: HorizontalAlignment::Left,
+            vert_align: VerticalAlignment::Top
+        }
+    }
+    pub fn room(width: i32, height: i32) -> Self {
+        let mut components = Vec::new();
+        for x in 0..width {
+            for y in 0..height {
+                let terrain =
+                    if x == 0 || x == width - 1 || y == 0 || y == height - 1 {
+                        Terrain::Wall
+                    } else {
+                        Terrain::Floor
+                    };
+                components.push((Location::new(x, y), terrain));
+            }
+        }
+
+        FeatureBuilder::new(components)
+    }
+    pub fn room_circle(radius: i32) -> Self {
+        let mut components = Vec::new();
+        for x in -radius..radius+1 {
+            for y in -radius..radius+1 {
+                let terrain = 
+                    if FeatureBuilder::distance_from_center(x,y) == radius {
+                        Terrain::Wall
+                    }
+                    else if FeatureBuilder::distance_from_center(x,y) < radius {
+                        Terrain::Floor
+                    }
+                    else {
+                        Terrain::Nothing
+                    };
+                components.push((Location::new(x, y), terrain));
+            }
+        }
+
+        FeatureBuilder::new(components)
+    }
+    // Fills the ellipse inscribed in a width by height box. Tiles of the
+    // ellipse touching anything outside it, diagonally included, become its
+    // walls so the floor is always sealed in.
+    pub fn room_ellipse(width: i32, height: i32) -> Self {
+        let (rx, ry) = (width as f64 / 2.0, height as f64 / 2.0);
+        let inside = |x: i32, y: i32| {
+            let dx = (x as f64 + 0.5 - rx) / rx;
+            let dy = (y as f64 + 0.5 - ry) / ry;
+            x >= 0 && x < width && y >= 0 && y < height && dx * dx + dy * dy <= 1.0
+        };
+
+        let mut components = Vec::new();
+        for x in 0..width {
+            for y in 0..height {
+                let terrain =
+                    if !inside(x, y) {
+                        Terrain::Nothing
+                    } else if ORTHOGONAL.iter().chain(DIAGONAL.iter())
+                        .any(|offset| !inside(x + offset.dx, y + offset.dy)) {
+                        Terrain::Wall
+                    } else {
+                        Terrain::Floor
+                    };
+                components.push((Location::new(x, y), terrain));
+            }
+        }
+
+        FeatureBuilder::new(components)
+    }
+    // Thickens the walls inward, turning floor beside them into wall until
+    // they're `thickness` tiles deep. Small rooms can lose all their floor.
+    pub fn wall_thickness(mut self, thickness: i32) -> Self {
+        for _ in 1..thickness {
+            let walls: HashSet<_> = self.components.iter()
+                .filter(|c| c.1 == Terrain::Wall)
+                .map(|c| c.0)
+                .collect();
+            for component in self.components.iter_mut().filter(|c| c.1 == Terrain::Floor) {
+                if ORTHOGONAL.iter().chain(DIAGONAL.iter())
+                    .any(|&offset| walls.contains(&(component.0 + offset))) {
+                    component.1 = Terrain::Wall;
+                }
+            }
+        }
+        self
+    }
+    pub fn distance_from_center(xi: i32,yi: i32) -> i32 {
+        let c: i32 = (xi * xi) + (yi * yi);
+        let c = (c as f64).sqrt();
+        c as i32
+    }
+    pub fn location(mut self, loc: Location) -> Self {
+        self.location = loc;
+        self
+    }
+    pub fn horiz_align(mut self, align: HorizontalAlignment) -> Self {
+        self.horiz_align = align;
+        self
+    }
+    pub fn vert_align(mut self, align: VerticalAlignment) -> Self {
+        self.vert_align = align;
+        self
+    }
+    pub fn build(&self) -> Feature {
+        let bounds = Rect::bounding(self.components.iter().map(|c| &c.0));
+        let horiz = match self.horiz_align {
+            HorizontalAlignment::Left => self.location.x - bounds.x,
+            HorizontalAlignment::Center => self.location.x - (bounds.x + bounds.width / 2),
+            HorizontalAlignment::Right => self.location.x - (bounds.x + bounds.width - 1)
+        };
+        let vert = match self.vert_align {
+            VerticalAlignment::Top => self.location.y - bounds.y,
+            VerticalAlignment::Center => self.location.y - (bounds.y + bounds.height / 2),
+            VerticalAlignment::Bottom => self.location.y - (bounds.y + bounds.height - 1)
+        };
+
+        let offset = Offset::new(horiz, vert);
+        let comps = self.components.iter()
+            .map(|c| (c.0 + offset, c.1))
+            .collect();
+
+        Feature::new(comps)
+    }
+}
+
+// Wraps a search to remember the explored node with the lowest heuristic,
+// for when the search fails.
+struct TrackClosest<'a, S> {
+    search: S,
+    closest: &'a Cell<(i32, Location)>
+}
+
+impl<'a, S> astar::SearchProblem<Location, i32, NeighborIterator> for TrackClosest<'a, S>
+    where S: astar::SearchProblem<Location, i32, NeighborIterator> {
+    fn start(&self) -> Location {
+        self.search.start()
+    }
+    fn is_end(&self, loc: &Location) -> bool {
+        let heuristic = self.search.heuristic(loc);
+        if heuristic < self.closest.get().0 {
+            self.closest.set((heuristic, *loc));
+        }
+        self.search.is_end(loc)
+    }
+    fn heuristic(&self, loc: &Location) -> i32 {
+        self.search.heuristic(loc)
+    }
+    fn neighbors(&self, at: &Location) -> NeighborIterator {
+        self.search.neighbors(at)
+    }
+}
+
+// Wraps a search to stop expanding tiles once the budget runs out. The
+// search then just drains what's already queued and fails.
+struct LimitExpansions<'a, S> {
+    search: S,
+    budget: &'a Cell<usize>
+}
+
+impl<'a, S> astar::SearchProblem<Location, i32, NeighborIterator> for LimitExpansions<'a, S>
+    where S: astar::SearchProblem<Location, i32, NeighborIterator> {
+    fn start(&self) -> Location {
+        self.search.start()
+    }
+    fn is_end(&self, loc: &Location) -> bool {
+        self.search.is_end(loc)
+    }
+    fn heuristic(&self, loc: &Location) -> i32 {
+        self.search.heuristic(loc)
+    }
+    fn neighbors(&self, at: &Location) -> NeighborIterator {
+        let left = self.budget.get();
+        if left == 0 {
+            return NeighborIterator { adjacent: [(*at, 0); 8], len: 0, current: 0 };
+        }
+        self.budget.set(left - 1);
+        self.search.neighbors(at)
+    }
+}
+
+// 64-bit FNV-1a hashing parameters.
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+// Writes a number seven bits at a time, low bits first, with the top bit of
+// each byte set if more follow.
+fn write_varint(bytes: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        bytes.push((n as u8 & 0x7f) | 0x80);
+        n >>= 7;
+    }
+    bytes.push(n as u8);
+}
+
+fn read_varint<I: Iterator<Item=u8>>(bytes: &mut I) -> Option<u64> {
+    let mut n = 0;
+    for shift in (0..10).map(|i| i * 7) {
+        let byte = bytes.next()?;
+        n |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 { return Some(n); }
+    }
+
+    None
+}
+
+// Maps signed numbers onto unsigned ones so small negatives stay short:
+// 0, -1, 1, -2... become 0, 1, 2, 3...
+fn zigzag(n: i32) -> u64 {
+    ((n << 1) ^ (n >> 31)) as u32 as u64
+}
+
+fn unzigzag(n: u64) -> Option<i32> {
+    if n > u32::max_value() as u64 { return None; }
+    let n = n as u32;
+    Some(((n >> 1) as i32) ^ -((n & 1) as i32))
+}
+
+// Builds a StdRng from a 64-bit seed, the same seed always giving the same
+// numbers.
+pub fn seeded_rng(seed: u64) -> StdRng {
+    let seed: &[_] = &[(seed & 0xffffffff) as usize, (seed >> 32) as usize];
+    SeedableRng::from_seed(seed)
+}
+
+// Trait to extend iterators to provide a random function.
+trait IterRandomExt<T> {
+    fn random<R: Rng>(&mut self, rng: &mut R) -> T;
+    // Up to n distinct elements in random order; fewer if the iterator is
+    // shorter.
+    fn random_n<R: Rng>(&mut self, rng: &mut R, n: usize) -> Vec<T>;
+}
+
+impl<I> IterRandomExt<I::Item> for I where I: Iterator, I::Item: Clone {
+    fn random<R: Rng>(&mut self, rng: &mut R) -> I::Item {
+        let elements: Vec<_> = self.collect();
+        assert!(elements.len() > 0);
+        let random = rng.gen_range::<usize>(0, elements.len());
+        elements[random].clone()
+    }
+    fn random_n<R: Rng>(&mut self, rng: &mut R, n: usize) -> Vec<I::Item> {
+        // A partial Fisher-Yates shuffle of just the first n slots.
+        let mut elements: Vec<_> = self.collect();
+        let n = n.min(elements.len());
+        for i in 0..n {
+            let j = rng.gen_range::<usize>(i, elements.len());
+            elements.swap(i, j);
+        }
+        elements.truncate(n);
+        elements
+    }
+}
+
+// Iterates through neighbors; used for A* algorithm.
+pub struct NeighborIterator {
+    // Room for all eight neighbors, so no allocation is needed.
+    adjacent: [(Location, i32); 8],
+    len: usize,
+    current: usize
+}
+
+impl NeighborIterator {
+    // Each step costs the base step distance scaled by what `cost` says
+    // about the location being entered; neighbors it maps to None are
+    // skipped, as are ledges approached from the wrong side.
+    pub fn new<F>(world: &WorldMap, loc: Location, movement: Movement, cost: F) -> Self
+        where F: Fn(Location) -> Option<i32> {
+        let enterable = |next: Location| world.get_tile(next).terrain.can_enter(next - loc);
+        let mut adjacent = [(loc, 0); 8];
+        let mut len = 0;
+        for next in loc.orthogonal().filter(|next| world.in_bounds(*next) && enterable(*next)) {
+            if let Some(multiplier) = cost(next) {
+                adjacent[len] = (next, ORTHOGONAL_COST * multiplier);
+                len += 1;
+            }
+        }
+        if movement == Movement::Diagonal {
+            for &offset in DIAGONAL.iter() {
+                let corner = loc + offset;
+                if !world.in_bounds(corner) || !enterable(corner) { continue; }
+                let multiplier = match cost(corner) {
+                    Some(multiplier) => multiplier,
+                    None => continue
+                };
+
+                // Don't squeeze between two blocked tiles.
+                let side_a = Location::new(corner.x, loc.y);
+                let side_b = Location::new(loc.x, corner.y);
+                if cost(side_a).is_some() || cost(side_b).is_some() {
+                    adjacent[len] = (corner, DIAGONAL_COST * multiplier);
+                    len += 1;
+                }
+            }
+        }
+
+        NeighborIterator { adjacent: adjacent, len: len, current: 0 }
+    }
+}
+
+impl Iterator for NeighborIterator {
+    type Item = (Location, i32);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.current < self.len {
+            self.current += 1;
+            Some(self.adjacent[self.current - 1])
+        } else {
+            None
+        }
+    }
+}
+
+// A* search over the map from a start to the first location the goal
+// accepts. Each step costs a multiple of its length given by the cost
+// closure, or can't be taken if that gives None. Without a target to head
+// toward the search spreads out evenly, like Dijkstra's algorithm.
+pub struct GridSearch<'a, G, C> where G: Fn(Location) -> bool, C: Fn(Location) -> Option<i32> {
+    world: &'a WorldMap,
+    start: Location,
+    goal: G,
+    cost: C,
+    target: Option<Location>,
+    movement: Movement
+}
+
+impl<'a, G, C> GridSearch<'a, G, C> where G: Fn(Location) -> bool, C: Fn(Location) -> Option<i32> {
+    pub fn new(world: &'a WorldMap, start: Location, goal: G, cost: C) -> Self {
+        GridSearch {
+            world: world,
+            start: start,
+            goal: goal,
+            cost: cost,
+            target: None,
+            movement: Movement::Orthogonal
+        }
+    }
+    // Guides the search toward a location. The cost closure mustn't give
+    // multiples below 1, or paths found this way may not be the shortest.
+    pub fn toward(mut self, target: Location) -> Self {
+        self.target = Some(target);
+        self
+    }
+    pub fn movement(mut self, movement: Movement) -> Self {
+        self.movement = movement;
+        self
+    }
+}
+
+impl<'a, G, C> astar::SearchProblem<Location, i32, NeighborIterator> for GridSearch<'a, G, C>
+    where G: Fn(Location) -> bool, C: Fn(Location) -> Option<i32> {
+    fn start(&self) -> Location {
+        self.start
+    }
+    fn is_end(&self, loc: &Location) -> bool {
+        (self.goal)(*loc)
+    }
+    fn heuristic(&self, loc: &Location) -> i32 {
+        match self.target {
+            Some(target) => self.movement.distance(loc, &target),
+            None => 0
+        }
+    }
+    fn neighbors(&self, at: &Location) -> NeighborIterator {
+        NeighborIterator::new(self.world, *at, self.movement, |loc| (self.cost)(loc))
+    }
+}
+
+#[test]
+fn test_feature_size() {
+    let feature = Feature::new(vec![
+        (Location::new(0, 0), Terrain::Wall),
+        (Location::new(1, 0), Terrain::Wall),
+        (Location::new(1, 1), Terrain::Wall)]);
+    assert_eq!(feature.width(), 2);
+    assert_eq!(feature.height(), 2);
+}
+
+#[test]
+fn test_build_feature() {
+    // Set up some feature shape:
+    //
+    // ....
+    // .##.
+    // .##.
+    // .#..
+    // ....
+    let comps = vec![
+        (Location::new(1, 1), Terrain::Wall),
+        (Location::new(2, 1), Terrain::Wall),
+        (Location::new(1, 2), Terrain::Wall),
+        (Location::new(2, 2), Terrain::Wall),
+        (Location::new(1, 3), Terrain::Wall)
+    ];
+
+    // Place top left at (2,3).
+    //
     // .....
     // .....
     // .....
@@ -568,69 +2621,1652 @@ fn test_build_feature() {
         .location(Location::new(2, 3))
         .build().components,
         vec![
-            (Location::new(2, 3), Terrain::Wall),
-            (Location::new(3, 3), Terrain::Wall),
-            (Location::new(2, 4), Terrain::Wall),
-            (Location::new(3, 4), Terrain::Wall),
-            (Location::new(2, 5), Terrain::Wall)
+            (Location::new(2, 3), Terrain::Wall),
+            (Location::new(3, 3), Terrain::Wall),
+            (Location::new(2, 4), Terrain::Wall),
+            (Location::new(3, 4), Terrain::Wall),
+            (Location::new(2, 5), Terrain::Wall)
+        ]);
+
+    // Place bottom right at (5,2).
+    //
+    // ....##.
+    // ....##.
+    // ....#..
+    // .......
+    assert_eq!(FeatureBuilder::new(comps)
+        .vert_align(VerticalAlignment::Bottom)
+        .horiz_align(HorizontalAlignment::Right)
+        .location(Location::new(5, 2))
+        .build().components,
+        vec![
+            (Location::new(4, 0), Terrain::Wall),
+            (Location::new(5, 0), Terrain::Wall),
+            (Location::new(4, 1), Terrain::Wall),
+            (Location::new(5, 1), Terrain::Wall),
+            (Location::new(4, 2), Terrain::Wall)
+        ]);
+
+    // Set up a square feature shape.
+    //
+    // ###
+    // ###
+    // ###
+    let square = vec![
+        (Location::new(0, 0), Terrain::Wall),
+        (Location::new(1, 0), Terrain::Wall),
+        (Location::new(2, 0), Terrain::Wall),
+        (Location::new(0, 1), Terrain::Wall),
+        (Location::new(1, 1), Terrain::Wall),
+        (Location::new(2, 1), Terrain::Wall),
+        (Location::new(0, 2), Terrain::Wall),
+        (Location::new(1, 2), Terrain::Wall),
+        (Location::new(2, 2), Terrain::Wall),
+    ];
+
+    // Place center at (4, 1).
+    //
+    // ...###.
+    // ...###.
+    // ...###.
+    // .......
+    assert_eq!(FeatureBuilder::new(square)
+        .vert_align(VerticalAlignment::Center)
+        .horiz_align(HorizontalAlignment::Center)
+        .location(Location::new(4, 1))
+        .build().components,
+        vec![
+            (Location::new(3, 0), Terrain::Wall),
+            (Location::new(4, 0), Terrain::Wall),
+            (Location::new(5, 0), Terrain::Wall),
+            (Location::new(3, 1), Terrain::Wall),
+            (Location::new(4, 1), Terrain::Wall),
+            (Location::new(5, 1), Terrain::Wall),
+            (Location::new(3, 2), Terrain::Wall),
+            (Location::new(4, 2), Terrain::Wall),
+            (Location::new(5, 2), Terrain::Wall)
         ]);
+}
+
+#[test]
+fn test_generate_connects_all_rooms() {
+    use std::collections::VecDeque;
+
+    for seed in 0..10 {
+        let seed: &[_] = &[seed];
+        let mut rng: StdRng = SeedableRng::from_seed(seed);
+        let (world, start) = WorldMap::generate(&mut rng, 80, 50);
+
+        // Every floor tile should be reachable from the start.
+        let mut seen = HashSet::new();
+        let mut queue = VecDeque::new();
+        seen.insert(start);
+        queue.push_back(start);
+        while let Some(loc) = queue.pop_front() {
+            for next in world.neighbors(loc, false) {
+                if world.get_tile(next).terrain.is_walkable() && seen.insert(next) {
+                    queue.push_back(next);
+                }
+            }
+        }
+        for (tile, loc) in world.tiles() {
+            if tile.terrain == Terrain::Floor {
+                assert!(seen.contains(&loc), "{:?} is unreachable", loc);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_random_reaches_every_element() {
+    let seed: &[_] = &[42];
+    let mut rng: StdRng = SeedableRng::from_seed(seed);
+    let mut counts = [0; 3];
+    for _ in 0..10000 {
+        counts[vec![0, 1, 2].into_iter().random(&mut rng)] += 1;
+    }
+    for count in counts.iter() {
+        assert!(*count > 0);
+    }
+
+    // A single element is always picked.
+    assert_eq!(vec![7].into_iter().random(&mut rng), 7);
+}
+
+#[test]
+fn test_try_get_tile_bounds() {
+    let seed: &[_] = &[1];
+    let mut rng: StdRng = SeedableRng::from_seed(seed);
+    let (mut world, _) = WorldMap::generate(&mut rng, 80, 50);
+    assert!(world.try_get_tile(Location::new(0, 0)).is_some());
+    assert!(world.try_get_tile(Location::new(79, 49)).is_some());
+    assert!(world.try_get_tile(Location::new(-1, 0)).is_none());
+    assert!(world.try_get_tile(Location::new(0, -1)).is_none());
+    assert!(world.try_get_tile(Location::new(80, 0)).is_none());
+    assert!(world.try_get_tile_mut(Location::new(0, 50)).is_none());
+}
+
+#[test]
+fn test_generate_has_no_debug_terrain() {
+    let seed: &[_] = &[5];
+    let mut rng: StdRng = SeedableRng::from_seed(seed);
+    let (world, _) = WorldMap::generate(&mut rng, 80, 50);
+    assert!(world.tiles().all(|(tile, _)| tile.terrain != Terrain::Debug));
+}
+
+#[test]
+fn test_location_offset_arithmetic() {
+    let a = Location::new(3, 4);
+    let b = Location::new(1, 7);
+    assert_eq!(a + Offset::new(-2, 3), b);
+    assert_eq!(b - Offset::new(-2, 3), a);
+    assert_eq!(b - a, Offset::new(-2, 3));
+}
+
+#[test]
+fn test_walk_path_follows_floor() {
+    // Floor corridor bending around a wall:
+    //
+    // ...#
+    // #.##
+    // #...
+    let mut world = WorldMap::new(4, 3);
+    for &(x, y) in [(0, 0), (1, 0), (2, 0), (1, 1), (1, 2), (2, 2), (3, 2)].iter() {
+        world.get_tile_mut(Location::new(x, y)).terrain = Terrain::Floor;
+    }
+    for &(x, y) in [(3, 0), (0, 1), (2, 1), (3, 1), (0, 2)].iter() {
+        world.get_tile_mut(Location::new(x, y)).terrain = Terrain::Wall;
+    }
+
+    let path = world.find_path(Location::new(0, 0), Location::new(3, 2)).unwrap();
+    assert_eq!(path, vec![
+        Location::new(0, 0),
+        Location::new(1, 0),
+        Location::new(1, 1),
+        Location::new(1, 2),
+        Location::new(2, 2),
+        Location::new(3, 2)]);
+
+    // The wall in the way can't be walked through.
+    assert!(world.find_path(Location::new(2, 0), Location::new(3, 0)).is_none());
+}
+
+#[test]
+fn test_find_path() {
+    // .#.
+    // ...
+    let mut world = WorldMap::new(3, 2);
+    for (x, y) in (0..3).flat_map(|x| (0..2).map(move |y| (x, y))) {
+        world.get_tile_mut(Location::new(x, y)).terrain = Terrain::Floor;
+    }
+    world.get_tile_mut(Location::new(1, 0)).terrain = Terrain::Wall;
+
+    let path = world.find_path(Location::new(0, 0), Location::new(2, 0)).unwrap();
+    assert_eq!(path.len(), 5);
+    assert_eq!(path[0], Location::new(0, 0));
+    assert_eq!(path[4], Location::new(2, 0));
+    assert!(path.iter().all(|loc| world.get_tile(*loc).terrain.is_walkable()));
+    assert!(world.find_path(Location::new(0, 0), Location::new(1, 0)).is_none());
+}
+
+#[test]
+fn test_find_path_diagonal() {
+    // ...
+    // ...
+    // ...
+    // All open floor, so the path cuts straight across the diagonal.
+    let mut world = WorldMap::new(3, 3);
+    for (x, y) in (0..3).flat_map(|x| (0..3).map(move |y| (x, y))) {
+        world.get_tile_mut(Location::new(x, y)).terrain = Terrain::Floor;
+    }
+    let path = world.find_path_with_movement(
+        Location::new(0, 0), Location::new(2, 2), Movement::Diagonal).unwrap();
+    assert_eq!(path, vec![Location::new(0, 0), Location::new(1, 1), Location::new(2, 2)]);
+
+    // .#.
+    // #..
+    // ...
+    // With both sides of the corner blocked there's no squeezing through,
+    // and no other way out of the corner, so no path exists.
+    world.get_tile_mut(Location::new(1, 0)).terrain = Terrain::Wall;
+    world.get_tile_mut(Location::new(0, 1)).terrain = Terrain::Wall;
+    assert!(world.find_path_with_movement(
+        Location::new(0, 0), Location::new(1, 1), Movement::Diagonal).is_none());
+
+    // .#.
+    // ...
+    // ...
+    // One open side is enough to cut the corner.
+    world.get_tile_mut(Location::new(0, 1)).terrain = Terrain::Floor;
+    let path = world.find_path_with_movement(
+        Location::new(0, 0), Location::new(1, 1), Movement::Diagonal).unwrap();
+    assert_eq!(path, vec![Location::new(0, 0), Location::new(1, 1)]);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde_round_trip() {
+    let seed: &[_] = &[3];
+    let mut rng: StdRng = SeedableRng::from_seed(seed);
+    let (mut world, start) = WorldMap::generate(&mut rng, 80, 50);
+    world.tag_region(&[start], "start");
+    let json = serde_json::to_string(&world).unwrap();
+    let loaded: WorldMap = serde_json::from_str(&json).unwrap();
+    assert_eq!(loaded.tiles_with_tag("start"), vec![start]);
+    assert_eq!(loaded.width(), world.width());
+    assert_eq!(loaded.height(), world.height());
+    for ((a, a_loc), (b, b_loc)) in world.tiles().zip(loaded.tiles()) {
+        assert_eq!(a_loc, b_loc);
+        assert_eq!(a, b);
+    }
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_rle_smaller_than_json() {
+    let (world, _) = WorldMap::generate(&mut seeded_rng(3), 80, 50);
+    let json = serde_json::to_string(&world).unwrap();
+    assert!(world.to_rle_bytes().len() * 10 < json.len());
+}
+
+#[test]
+fn test_rle_round_trip() {
+    let (mut world, start) = WorldMap::generate(&mut seeded_rng(3), 80, 50);
+    world.update_visibility(start, 6);
+    let monster = world.spawn_entity(start);
+    let item = world.spawn_item(start);
+    let other = world.spawn_entity(Location::new(0, 0));
+    world.remove_entity(Location::new(0, 0), other.id());
+    world.tag_region(&[start, Location::new(0, 0)], "camp");
+
+    let bytes = world.to_rle_bytes();
+    assert!(bytes.len() < 2000);
+    let loaded = WorldMap::from_rle_bytes(&bytes).unwrap();
+    assert_eq!((loaded.width(), loaded.height()), (80, 50));
+    for ((a, a_loc), (b, b_loc)) in world.tiles().zip(loaded.tiles()) {
+        assert_eq!(a_loc, b_loc);
+        assert_eq!(a, b);
+    }
+    assert_eq!(loaded.rooms(), world.rooms());
+    assert_eq!(loaded.find_entity(monster.id()), Some(start));
+    assert_eq!(loaded.find_entity(item.id()), Some(start));
+    assert_eq!(loaded.tiles_with_tag("camp"), world.tiles_with_tag("camp"));
+    assert_eq!(loaded.to_rle_bytes(), bytes);
+    assert_eq!(loaded.validate(), Ok(()));
+
+    assert!(WorldMap::from_rle_bytes(&bytes[..bytes.len() - 1]).is_none());
+    assert!(WorldMap::from_rle_bytes(&[bytes.clone(), vec![0]].concat()).is_none());
+
+    // A 2x1 floor with entity 0 on both tiles, then with 1 of only 1 spawned.
+    let duplicate = [2, 1, 2, 1, b'.', 0, 2, 2, 0, 0, 1, 0, 1, 1, 0, 0];
+    assert!(WorldMap::from_rle_bytes(&duplicate).is_none());
+    let unspawned = [2, 1, 1, 1, b'.', 0, 2, 1, 1, 0, 1, 0, 0];
+    assert!(WorldMap::from_rle_bytes(&unspawned).is_none());
+    let fine = [2, 1, 2, 1, b'.', 0, 2, 1, 1, 0, 1, 0, 0];
+    assert!(WorldMap::from_rle_bytes(&fine).is_some());
+
+    // A 2^31-1 square header with a single tile's worth of runs.
+    let huge = [0xff, 0xff, 0xff, 0xff, 0x07, 0xff, 0xff, 0xff, 0xff, 0x07,
+                0, 1, b'.', 0, 1, 0, 0, 0];
+    assert!(WorldMap::from_rle_bytes(&huge).is_none());
+    assert!(WorldMap::from_rle_bytes(&[]).is_none());
+}
+
+#[test]
+fn test_zigzag() {
+    for &n in [0, 1, -1, 63, -64, i32::max_value(), i32::min_value()].iter() {
+        assert_eq!(unzigzag(zigzag(n)), Some(n));
+    }
+    assert_eq!(zigzag(-1), 1);
+    assert_eq!(zigzag(1), 2);
+}
+
+#[test]
+fn test_to_ascii() {
+    let mut world = WorldMap::new(3, 2);
+    world.get_tile_mut(Location::new(0, 0)).terrain = Terrain::Wall;
+    world.get_tile_mut(Location::new(1, 0)).terrain = Terrain::Floor;
+    world.get_tile_mut(Location::new(2, 1)).terrain = Terrain::Debug;
+    assert_eq!(world.to_ascii(), "#. \n  ?\n");
+}
+
+#[test]
+fn test_terrain_chars_round_trip() {
+    let all = [Terrain::Debug, Terrain::Nothing, Terrain::Floor, Terrain::Wall, Terrain::Door,
+               Terrain::Rubble, Terrain::StairsUp, Terrain::StairsDown, Terrain::Grass,
+               Terrain::Water, Terrain::Chasm, Terrain::Fog,
+               Terrain::Ledge(Direction::North), Terrain::Ledge(Direction::East),
+               Terrain::Ledge(Direction::South), Terrain::Ledge(Direction::West)];
+    for terrain in all.iter() {
+        assert_eq!(Terrain::from_char(terrain.to_char()), Some(*terrain));
+    }
+    assert_eq!(Terrain::from_char('x'), None);
+}
+
+#[test]
+fn test_from_ascii() {
+    let ascii = "#####\n#.<.#\n#+###\n";
+    let world = WorldMap::from_ascii(ascii).unwrap();
+    assert_eq!(world.width(), 5);
+    assert_eq!(world.height(), 3);
+    assert_eq!(world.get_tile(Location::new(2, 1)).terrain, Terrain::StairsUp);
+    assert_eq!(world.to_ascii(), ascii);
+
+    assert!(WorldMap::from_ascii("").is_none());
+    assert!(WorldMap::from_ascii("##\n#\n").is_none());
+    assert!(WorldMap::from_ascii("#x#\n").is_none());
+}
+
+#[test]
+fn test_location_distances() {
+    let a = Location::new(1, 2);
+    let b = Location::new(4, -2);
+    assert_eq!(a.manhattan(&b), 7);
+    assert_eq!(a.chebyshev(&b), 4);
+    assert_eq!(b.chebyshev(&a), 4);
+    assert_eq!(a.euclidean_squared(&b), 25);
+    assert_eq!(a.chebyshev(&a), 0);
+}
+
+#[test]
+fn test_generate_seeded_is_reproducible() {
+    let (world, start, seed) = WorldMap::generate_seeded(None, 80, 50);
+    let (again, again_start, again_seed) = WorldMap::generate_seeded(Some(seed), 80, 50);
+    assert_eq!(again_seed, seed);
+    assert_eq!(again_start, start);
+    assert_eq!(again.to_ascii(), world.to_ascii());
+}
+
+#[test]
+fn test_generate_tiny_maps() {
+    for seed in 0..200 {
+        let (world, start) = WorldMap::generate(&mut seeded_rng(seed), 10, 10);
+        assert!(world.get_tile(start).terrain.is_walkable());
+    }
+
+    // Nothing but the smallest room fits.
+    let (world, start) = WorldMap::generate(&mut seeded_rng(0), 3, 3);
+    assert_eq!(start, Location::new(1, 1));
+    assert_eq!(world.get_tile(start).terrain, Terrain::Floor);
+}
+
+#[test]
+fn test_feature_overlaps() {
+    let room_at = |x, y| FeatureBuilder::room(3, 3).location(Location::new(x, y)).build();
+
+    // Touching: ###### (walls side by side)
+    assert!(!room_at(0, 0).overlaps(&room_at(3, 0), 0));
+    assert!(room_at(0, 0).overlaps(&room_at(3, 0), 1));
+
+    // One-tile gap: ### ###
+    assert!(!room_at(0, 0).overlaps(&room_at(4, 0), 0));
+    assert!(!room_at(0, 0).overlaps(&room_at(4, 0), 1));
+    assert!(room_at(0, 0).overlaps(&room_at(4, 0), 2));
+
+    // Sharing a wall.
+    assert!(room_at(0, 0).overlaps(&room_at(2, 0), 0));
+    assert!(room_at(2, 1).overlaps(&room_at(0, 0), 0));
+}
+
+#[test]
+fn test_feature_overlaps_sharing_walls() {
+    let room_at = |x, y| FeatureBuilder::room(4, 4).location(Location::new(x, y)).build();
+
+    assert!(!room_at(0, 0).overlaps_sharing_walls(&room_at(3, 0)));
+    assert!(!room_at(0, 0).overlaps_sharing_walls(&room_at(3, 3)));
+    assert!(!room_at(0, 0).overlaps_sharing_walls(&room_at(4, 0)));
+    // A wall on the other room's floor.
+    assert!(room_at(0, 0).overlaps_sharing_walls(&room_at(2, 0)));
+    assert!(room_at(0, 0).overlaps_sharing_walls(&room_at(0, 0)));
+}
+
+#[test]
+fn test_place_feature() {
+    let square = FeatureBuilder::room(3, 3)
+        .vert_align(VerticalAlignment::Center)
+        .horiz_align(HorizontalAlignment::Center)
+        .location(Location::new(1, 1))
+        .build();
+    let mut world = WorldMap::new(4, 4);
+    world.place_feature(&square);
+    assert_eq!(world.to_ascii(), "### \n#.# \n### \n    \n");
+
+    // Parts hanging off the map are dropped.
+    let mut world = WorldMap::new(2, 2);
+    world.place_feature(&square);
+    assert_eq!(world.to_ascii(), "##\n#.\n");
+}
+
+#[test]
+fn test_generate_places_doors() {
+    let (world, _) = WorldMap::generate(&mut seeded_rng(7), 80, 50);
+    let doors: Vec<_> = world.tiles()
+        .filter(|&(tile, _)| tile.terrain == Terrain::Door)
+        .map(|(_, loc)| loc)
+        .collect();
+    assert!(doors.len() > 0);
+
+    // Each door joins a room to a corridor.
+    for door in doors {
+        let floors = world.neighbors(door, false).into_iter()
+            .filter(|loc| world.get_tile(*loc).terrain == Terrain::Floor)
+            .count();
+        assert!(floors >= 2, "Door at {:?} leads nowhere", door);
+    }
+}
+
+#[test]
+fn test_dijkstra_map() {
+    // ....#
+    // .##.#
+    // ...#.
+    let mut world = WorldMap::new(5, 3);
+    for (x, y) in (0..5).flat_map(|x| (0..3).map(move |y| (x, y))) {
+        world.get_tile_mut(Location::new(x, y)).terrain = Terrain::Floor;
+    }
+    for &(x, y) in [(4, 0), (1, 1), (2, 1), (4, 1), (3, 2)].iter() {
+        world.get_tile_mut(Location::new(x, y)).terrain = Terrain::Wall;
+    }
+
+    let distances = world.dijkstra_map(&[Location::new(0, 0), Location::new(2, 2)]);
+    assert_eq!(distances, vec![
+        Some(0), Some(1), Some(2), Some(3), None,
+        Some(1), None, None, Some(4), None,
+        Some(2), Some(1), Some(0), None, None]);
+}
+
+#[test]
+fn test_connected_regions() {
+    let mut world = WorldMap::new(12, 5);
+    world.place_feature(&FeatureBuilder::room(5, 5).build());
+    world.place_feature(&FeatureBuilder::room(4, 3).location(Location::new(7, 1)).build());
+
+    let regions = world.connected_regions();
+    assert_eq!(regions.len(), 2);
+    assert_eq!(regions[0].len(), 9);
+    assert_eq!(regions[1].len(), 2);
+    assert!(regions[1].contains(&Location::new(8, 2)));
+    assert!(regions[1].contains(&Location::new(9, 2)));
+}
+
+#[test]
+fn test_spawn_and_remove_entity() {
+    let mut world = WorldMap::new(3, 3);
+    let here = Location::new(1, 1);
+    let a = world.spawn_entity(here);
+    let b = world.spawn_entity(here);
+    assert!(a.id() != b.id());
+    assert_eq!(world.get_tile(here).entities, vec![a, b]);
+
+    assert!(world.remove_entity(here, a.id()));
+    assert!(!world.remove_entity(here, a.id()));
+    assert!(!world.remove_entity(Location::new(0, 0), b.id()));
+    assert_eq!(world.get_tile(here).entities, vec![b]);
+
+    // Ids aren't reused after removal.
+    let c = world.spawn_entity(here);
+    assert!(c.id() != a.id() && c.id() != b.id());
+}
+
+#[test]
+fn test_move_entity() {
+    // .#
+    // ..
+    let mut world = WorldMap::new(2, 2);
+    for (x, y) in (0..2).flat_map(|x| (0..2).map(move |y| (x, y))) {
+        world.get_tile_mut(Location::new(x, y)).terrain = Terrain::Floor;
+    }
+    world.get_tile_mut(Location::new(1, 0)).terrain = Terrain::Wall;
+    let start = Location::new(0, 0);
+    let entity = world.spawn_entity(start);
+
+    assert_eq!(world.move_entity(entity.id(), start, Location::new(1, 0)),
+               Err(MoveError::NotWalkable));
+    assert_eq!(world.move_entity(entity.id(), start, Location::new(-1, 0)),
+               Err(MoveError::OutOfBounds));
+    assert_eq!(world.move_entity(entity.id(), Location::new(0, 1), start),
+               Err(MoveError::NotFound));
+    assert_eq!(world.move_entity(entity.id() + 1, start, Location::new(0, 1)),
+               Err(MoveError::NotFound));
+    assert_eq!(world.get_tile(start).entities, vec![entity]);
+
+    let end = Location::new(1, 1);
+    assert_eq!(world.move_entity(entity.id(), start, end), Ok(()));
+    assert!(world.get_tile(start).entities.is_empty());
+    assert_eq!(world.get_tile(end).entities, vec![entity]);
+}
+
+#[test]
+fn test_index_round_trip() {
+    let world = WorldMap::new(7, 5);
+    for (index, (_, loc)) in world.tiles().enumerate() {
+        assert_eq!(world.index_of(loc), index);
+        assert_eq!(world.location_of(index), loc);
+    }
+    assert_eq!(world.index_of(Location::new(2, 3)), 23);
+    assert_eq!(world.location_of(34), Location::new(6, 4));
+}
+
+#[test]
+fn test_neighbors_stay_in_bounds() {
+    let world = WorldMap::new(3, 3);
+    let sorted = |mut locs: Vec<Location>| {
+        locs.sort_by_key(|loc| (loc.y, loc.x));
+        locs
+    };
+
+    assert_eq!(world.neighbors(Location::new(1, 1), false).len(), 4);
+    assert_eq!(world.neighbors(Location::new(1, 1), true).len(), 8);
+    assert_eq!(sorted(world.neighbors(Location::new(0, 0), true)),
+               vec![Location::new(1, 0), Location::new(0, 1), Location::new(1, 1)]);
+    assert_eq!(sorted(world.neighbors(Location::new(2, 2), false)),
+               vec![Location::new(2, 1), Location::new(1, 2)]);
+    assert_eq!(sorted(world.neighbors(Location::new(2, 0), true)),
+               vec![Location::new(1, 0), Location::new(1, 1), Location::new(2, 1)]);
+    assert_eq!(sorted(world.neighbors(Location::new(0, 2), true)),
+               vec![Location::new(0, 1), Location::new(1, 1), Location::new(1, 2)]);
+    assert_eq!(world.neighbors(Location::new(1, 0), true).len(), 5);
+    assert_eq!(world.neighbors(Location::new(0, 1), false).len(), 3);
+}
+
+#[test]
+fn test_try_generate_rejects_bad_dimensions() {
+    let mut rng = seeded_rng(0);
+    assert_eq!(WorldMap::try_generate(&mut rng, 0, 10).err(), Some(GenError::InvalidDimensions));
+    assert_eq!(WorldMap::try_generate(&mut rng, 10, -3).err(), Some(GenError::InvalidDimensions));
+    assert_eq!(WorldMap::try_generate(&mut rng, 2, 10).err(), Some(GenError::MapTooSmall));
+
+    let mut config = WorldMapConfig::default();
+    config.min_room_size = 2;
+    assert_eq!(WorldMap::try_generate_with_config(&mut rng, 10, 10, &config).err(),
+               Some(GenError::InvalidConfig));
+    assert!(WorldMap::try_generate(&mut rng, 10, 10).is_ok());
+}
+
+#[test]
+fn test_find_path_avoids_costly_terrain() {
+    // ....
+    // .::.
+    // ....
+    let mut world = WorldMap::new(4, 3);
+    for (x, y) in (0..4).flat_map(|x| (0..3).map(move |y| (x, y))) {
+        world.get_tile_mut(Location::new(x, y)).terrain = Terrain::Floor;
+    }
+    world.get_tile_mut(Location::new(1, 1)).terrain = Terrain::Rubble;
+    world.get_tile_mut(Location::new(2, 1)).terrain = Terrain::Rubble;
+
+    // Straight through costs 3 + 3 + 1 steps; around the rubble costs 5.
+    let path = world.find_path(Location::new(0, 1), Location::new(3, 1)).unwrap();
+    assert_eq!(path.len(), 6);
+    assert!(path.iter().all(|loc| world.get_tile(*loc).terrain == Terrain::Floor));
+
+    let distances = world.dijkstra_map(&[Location::new(0, 1)]);
+    assert_eq!(distances[world.index_of(Location::new(1, 1))], Some(3));
+    assert_eq!(distances[world.index_of(Location::new(3, 1))], Some(5));
+}
+
+#[test]
+fn test_room_ellipse() {
+    let feature = FeatureBuilder::room_ellipse(11, 7).build();
+    assert_eq!(feature.width(), 11);
+    assert_eq!(feature.height(), 7);
+
+    // The floor is sealed in by walls, and the corners of the box are cut.
+    let solid: HashSet<Location> =
+        feature.walls().chain(feature.floors()).cloned().collect();
+    assert!(feature.floors().count() > 0);
+    for floor in feature.floors() {
+        let mut around = ORTHOGONAL.iter().chain(DIAGONAL.iter());
+        assert!(around.all(|offset| solid.contains(&(*floor + *offset))));
+    }
+    assert!(!solid.contains(&Location::new(0, 0)));
+    assert!(!solid.contains(&Location::new(10, 6)));
+
+    let config = WorldMapConfig {
+        room_shapes: vec![(RoomShape::Elliptical, 1)],
+        .. WorldMapConfig::default()
+    };
+    let mut rng = seeded_rng(7);
+    let (world, start) = WorldMap::generate_with_config(&mut rng, 80, 50, &config);
+    assert_eq!(world.get_tile(start).terrain, Terrain::Floor);
+    assert_eq!(world.connected_regions().len(), 1);
+}
+
+#[test]
+fn test_tiles_in_rect() {
+    let world = WorldMap::new(5, 4);
+    let locations: Vec<_> = world.tiles_in_rect(Location::new(1, 1), 2, 2)
+        .map(|(_, loc)| loc)
+        .collect();
+    assert_eq!(locations, vec![Location::new(1, 1), Location::new(2, 1),
+                               Location::new(1, 2), Location::new(2, 2)]);
+
+    // Clamped to the map rather than panicking.
+    assert_eq!(world.tiles_in_rect(Location::new(-3, -3), 100, 100).count(), 20);
+    assert_eq!(world.tiles_in_rect(Location::new(4, 3), 10, 10).count(), 1);
+    assert_eq!(world.tiles_in_rect(Location::new(6, 0), 2, 2).count(), 0);
+    assert_eq!(world.tiles_in_rect(Location::new(0, 0), -1, 2).count(), 0);
+}
+
+#[test]
+fn test_tiles_within() {
+    let world = WorldMap::new(10, 10);
+    let center = Location::new(5, 5);
+    assert_eq!(world.tiles_within(center, 0, Metric::Manhattan), vec![center]);
+    assert_eq!(world.tiles_within(center, 2, Metric::Manhattan).len(), 13);
+    assert_eq!(world.tiles_within(center, 2, Metric::Chebyshev).len(), 25);
+    assert_eq!(world.tiles_within(center, 2, Metric::Euclidean).len(), 13);
+    assert_eq!(world.tiles_within(center, 3, Metric::Euclidean).len(), 29);
+
+    // Row-major, and clipped at the edge of the map.
+    let corner = world.tiles_within(Location::new(0, 0), 1, Metric::Chebyshev);
+    assert_eq!(corner, vec![Location::new(0, 0), Location::new(1, 0),
+                            Location::new(0, 1), Location::new(1, 1)]);
+}
+
+#[test]
+fn test_generate_level_places_stairs() {
+    let config = WorldMapConfig::default();
+    for seed in 0..5 {
+        let mut rng = seeded_rng(seed);
+        let (world, up, down) = WorldMap::generate_level(&mut rng, 80, 50, &config).unwrap();
+        assert_eq!(world.get_tile(up).terrain, Terrain::StairsUp);
+        assert_eq!(world.get_tile(down).terrain, Terrain::StairsDown);
+        assert!(up != down);
+        assert!(world.find_path(up, down).is_some());
+    }
+
+    // A map with room for just one room can't have both stairs.
+    let mut rng = seeded_rng(0);
+    assert_eq!(WorldMap::generate_level(&mut rng, 3, 3, &config).err(),
+               Some(GenError::TooFewRooms));
+}
+
+#[test]
+fn test_drunken_corridors_connect_all_rooms() {
+    let config = WorldMapConfig {
+        corridor_style: CorridorStyle::Drunken,
+        .. WorldMapConfig::default()
+    };
+    for seed in 0..5 {
+        let mut rng = seeded_rng(seed);
+        let (world, start) = WorldMap::generate_with_config(&mut rng, 80, 50, &config);
+        assert_eq!(world.get_tile(start).terrain, Terrain::Floor);
+        assert_eq!(world.connected_regions().len(), 1);
+    }
+}
+
+#[test]
+fn test_find_entity() {
+    let mut world = WorldMap::new(3, 1);
+    for x in 0..3 {
+        world.get_tile_mut(Location::new(x, 0)).terrain = Terrain::Floor;
+    }
+    let (a, b) = (Location::new(0, 0), Location::new(2, 0));
+    let entity = world.spawn_entity(a);
+    let other = world.spawn_entity(a);
+    assert_eq!(world.entities_at(a), &[entity, other]);
+    assert_eq!(world.find_entity(entity.id()), Some(a));
+
+    world.move_entity(entity.id(), a, b).unwrap();
+    assert_eq!(world.entities_at(a), &[other]);
+    assert_eq!(world.entities_at(b), &[entity]);
+    assert_eq!(world.find_entity(entity.id()), Some(b));
+
+    assert!(world.remove_entity(b, entity.id()));
+    assert_eq!(world.find_entity(entity.id()), None);
+    assert_eq!(world.find_entity(other.id()), Some(a));
+    assert!(world.entities_at(Location::new(5, 5)).is_empty());
+}
+
+#[test]
+fn test_blocking_entities() {
+    let mut world = WorldMap::new(3, 1);
+    for x in 0..2 {
+        world.get_tile_mut(Location::new(x, 0)).terrain = Terrain::Floor;
+    }
+    let (a, b) = (Location::new(0, 0), Location::new(1, 0));
+    assert!(!world.is_occupied(a) && world.is_passable(a));
+    assert!(!world.is_passable(Location::new(2, 0)));
+
+    let item = world.spawn_item(a);
+    assert!(world.is_occupied(a) && world.is_passable(a));
+    let monster = world.spawn_entity(b);
+    assert!(world.is_occupied(b) && !world.is_passable(b));
+
+    // Items can share a tile with monsters, but monsters can't share.
+    let other = world.spawn_entity(a);
+    assert_eq!(world.move_entity(other.id(), a, b), Err(MoveError::Blocked));
+    assert_eq!(world.move_entity(item.id(), a, b), Ok(()));
+    assert_eq!(world.move_entity(monster.id(), b, a), Err(MoveError::Blocked));
+    world.remove_entity(a, other.id());
+    assert_eq!(world.move_entity(monster.id(), b, a), Ok(()));
+}
+
+#[test]
+fn test_rows() {
+    let mut world = WorldMap::new(3, 2);
+    world.get_tile_mut(Location::new(2, 1)).terrain = Terrain::Wall;
+    let rows: Vec<_> = world.rows().collect();
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[1].0, 1);
+    assert_eq!(rows[1].1.len(), 3);
+    assert_eq!(rows[1].1[2].terrain, Terrain::Wall);
+    assert_eq!(rows[0].1, &world.tiles[0..3]);
+}
+
+#[test]
+fn test_widen_corridor() {
+    let mut world = WorldMap::new(8, 5);
+    world.get_tile_mut(Location::new(4, 1)).terrain = Terrain::Wall;
+    let path: Vec<_> = (1..7).map(|x| Location::new(x, 2)).collect();
+    world.widen_corridor(&path, 3, Terrain::Floor);
+    let column: Vec<_> = (0..5).map(|y| world.get_tile(Location::new(3, y)).terrain).collect();
+    assert_eq!(column, vec![Terrain::Nothing, Terrain::Floor, Terrain::Floor,
+                            Terrain::Floor, Terrain::Nothing]);
+    assert_eq!(world.get_tile(Location::new(4, 1)).terrain, Terrain::Wall);
+
+    // Corridors along the edge of the map are clipped.
+    let edge: Vec<_> = (0..8).map(|x| Location::new(x, 4)).collect();
+    world.widen_corridor(&edge, 3, Terrain::Floor);
+    assert_eq!(world.get_tile(Location::new(0, 3)).terrain, Terrain::Floor);
+
+    let config = WorldMapConfig { corridor_width: 3, .. WorldMapConfig::default() };
+    let mut rng = seeded_rng(5);
+    let (world, _) = WorldMap::generate_with_config(&mut rng, 80, 50, &config);
+    assert_eq!(world.connected_regions().len(), 1);
+}
+
+#[test]
+fn test_nearest() {
+    let world = WorldMap::from_ascii("#######\n\
+                                      #>.#..#\n\
+                                      #..#.>#\n\
+                                      #######\n").unwrap();
+    let is_stairs = |tile: &Tile| tile.terrain == Terrain::StairsDown;
+    // Stairs on the other side of the wall don't count, being unreachable.
+    assert_eq!(world.nearest(Location::new(2, 2), &is_stairs), Some(Location::new(1, 1)));
+    assert_eq!(world.nearest(Location::new(4, 1), &is_stairs), Some(Location::new(5, 2)));
+    assert_eq!(world.nearest(Location::new(1, 1), &is_stairs), Some(Location::new(1, 1)));
+    assert_eq!(world.nearest(Location::new(2, 1), |tile| tile.terrain == Terrain::Door), None);
+}
+
+#[test]
+fn test_autoexplore_path() {
+    let world = WorldMap::from_ascii("######\n\
+                                      #....#\n\
+                                      ######\n").unwrap();
+    let mut explored = HashSet::new();
+    explored.insert(Location::new(1, 1));
+    explored.insert(Location::new(2, 1));
+    let path = world.autoexplore_path(Location::new(1, 1), &explored).unwrap();
+    assert_eq!(path, vec![Location::new(1, 1), Location::new(2, 1), Location::new(3, 1)]);
+
+    // Walls are never worth exploring.
+    explored.insert(Location::new(3, 1));
+    explored.insert(Location::new(4, 1));
+    assert!(world.autoexplore_path(Location::new(1, 1), &explored).is_none());
+}
 
-    // Place bottom right at (5,2).
-    //
-    // ....##.
-    // ....##.
-    // ....#..
-    // .......
-    assert_eq!(FeatureBuilder::new(comps)
-        .vert_align(VerticalAlignment::Bottom)
-        .horiz_align(HorizontalAlignment::Right)
-        .location(Location::new(5, 2))
-        .build().components,
-        vec![
-            (Location::new(4, 0), Terrain::Wall),
-            (Location::new(5, 0), Terrain::Wall),
-            (Location::new(4, 1), Terrain::Wall),
-            (Location::new(5, 1), Terrain::Wall),
-            (Location::new(4, 2), Terrain::Wall)
-        ]);
+#[test]
+fn test_rooms() {
+    let mut rng = seeded_rng(11);
+    let (world, start) = WorldMap::generate(&mut rng, 80, 50);
+    assert!(world.rooms().len() > 1);
+    assert!(world.rooms().iter().any(|room| room.contains(start)));
+    for room in world.rooms() {
+        assert!(world.get_tile(room.center()).terrain.is_walkable());
+    }
+    assert!(WorldMap::new(3, 3).rooms().is_empty());
+}
 
-    // Set up a square feature shape.
-    //
-    // ###
-    // ###
-    // ###
-    let square = vec![
-        (Location::new(0, 0), Terrain::Wall),
-        (Location::new(1, 0), Terrain::Wall),
-        (Location::new(2, 0), Terrain::Wall),
-        (Location::new(0, 1), Terrain::Wall),
-        (Location::new(1, 1), Terrain::Wall),
-        (Location::new(2, 1), Terrain::Wall),
-        (Location::new(0, 2), Terrain::Wall),
-        (Location::new(1, 2), Terrain::Wall),
-        (Location::new(2, 2), Terrain::Wall),
-    ];
+#[test]
+fn test_populate() {
+    let mut rng = seeded_rng(2);
+    let config = WorldMapConfig::default();
+    let (mut world, up, _) = WorldMap::generate_level(&mut rng, 80, 50, &config).unwrap();
+    let occupied = world.spawn_entity(up);
+    let spawned = world.populate(&mut rng, 1..4);
+    assert!(spawned.len() >= world.rooms().len());
+    assert!(spawned.len() <= 3 * world.rooms().len());
 
-    // Place center at (4, 1).
-    //
-    // ...###.
-    // ...###.
-    // ...###.
-    // .......
-    assert_eq!(FeatureBuilder::new(square)
-        .vert_align(VerticalAlignment::Center)
-        .horiz_align(HorizontalAlignment::Center)
-        .location(Location::new(4, 1))
-        .build().components,
-        vec![
-            (Location::new(3, 0), Terrain::Wall),
-            (Location::new(4, 0), Terrain::Wall),
-            (Location::new(5, 0), Terrain::Wall),
-            (Location::new(3, 1), Terrain::Wall),
-            (Location::new(4, 1), Terrain::Wall),
-            (Location::new(5, 1), Terrain::Wall),
-            (Location::new(3, 2), Terrain::Wall),
-            (Location::new(4, 2), Terrain::Wall),
-            (Location::new(5, 2), Terrain::Wall)
-        ]);
+    let mut seen = HashSet::new();
+    for &(entity, loc) in spawned.iter() {
+        assert_eq!(world.get_tile(loc).terrain, Terrain::Floor);
+        assert_eq!(world.find_entity(entity.id()), Some(loc));
+        assert!(seen.insert(loc));
+    }
+    assert_eq!(world.entities_at(up), &[occupied]);
+    assert!(world.populate(&mut rng, 0..0).is_empty());
+}
+
+#[test]
+fn test_grid_search_without_target() {
+    let world = WorldMap::from_ascii("#####\n\
+                                      #+..#\n\
+                                      #.#.#\n\
+                                      #..+#\n\
+                                      #####\n").unwrap();
+    let start = Location::new(2, 1);
+    let doors = |loc: Location| world.get_tile(loc).terrain == Terrain::Door;
+    let walkable = |loc: Location| world.get_tile(loc).terrain.move_cost();
+    let path = astar::astar(GridSearch::new(&world, start, &doors, &walkable)).unwrap();
+    assert_eq!(path.len(), 2);
+    assert_eq!(path[1], Location::new(1, 1));
+
+    // Steps costs can depend on where they lead.
+    let avoid = |loc: Location| if loc == Location::new(1, 1) { None } else { walkable(loc) };
+    let path = astar::astar(GridSearch::new(&world, start, &doors, &avoid)).unwrap();
+    assert_eq!(path.back(), Some(&Location::new(3, 3)));
+}
+
+#[test]
+fn test_wrap_walls() {
+    let mut world = WorldMap::from_ascii("##    \n\
+                                          #...  \n\
+                                          ##    \n").unwrap();
+    world.wrap_walls();
+    assert_eq!(world.to_ascii(), "##### \n\
+                                  #...# \n\
+                                  ##### \n");
+}
+
+#[test]
+fn test_world_map_builder() {
+    let mut rng = seeded_rng(4);
+    let (world, start) = WorldMapBuilder::new(80, 50)
+        .add_rooms(4)
+        .add_rooms(4)
+        .connect_rooms(CorridorStyle::Drunken)
+        .place_stairs()
+        .wrap_walls()
+        .build(&mut rng)
+        .unwrap();
+    assert_eq!(world.get_tile(start).terrain, Terrain::StairsUp);
+    assert!(world.rooms().len() > 1 && world.rooms().len() <= 8);
+    assert_eq!(world.connected_regions().len(), 1);
+    assert!(world.tiles().any(|(tile, _)| tile.terrain == Terrain::StairsDown));
+
+    // Without any rooms there's nowhere to start.
+    assert_eq!(WorldMapBuilder::new(80, 50).wrap_walls().build(&mut rng).err(),
+               Some(GenError::TooFewRooms));
+    assert_eq!(WorldMapBuilder::new(0, 50).build(&mut rng).err(),
+               Some(GenError::InvalidDimensions));
+}
+
+#[test]
+fn test_location_from_index() {
+    assert_eq!(location_from_index(0, 5), Location::new(0, 0));
+    assert_eq!(location_from_index(4, 5), Location::new(4, 0));
+    assert_eq!(location_from_index(5, 5), Location::new(0, 1));
+    assert_eq!(location_from_index(23, 5), Location::new(3, 4));
+    assert_eq!(location_from_index(3000000005, 100000), Location::new(5, 30000));
+}
+
+#[test]
+fn test_find_path_or_closest() {
+    let world = WorldMap::from_ascii("#######\n\
+                                      #...#.#\n\
+                                      #######\n").unwrap();
+    let (from, walled_off) = (Location::new(1, 1), Location::new(5, 1));
+    assert!(world.find_path(from, walled_off).is_none());
+    assert_eq!(world.find_path_or_closest(from, walled_off),
+               vec![from, Location::new(2, 1), Location::new(3, 1)]);
+    assert_eq!(world.find_path_or_closest(from, Location::new(2, 1)),
+               vec![from, Location::new(2, 1)]);
+    assert_eq!(world.find_path_or_closest(walled_off, from), vec![walled_off]);
+}
+
+#[test]
+fn test_transforms() {
+    let mut world = WorldMap::from_ascii("#.+\n\
+                                      .. \n").unwrap();
+    let entity = world.spawn_entity(Location::new(1, 0));
+    world.add_room(Rect::new(0, 0, 2, 1));
+
+    let rotated = world.rotated_90();
+    assert_eq!(rotated.to_ascii(), ".#\n..\n +\n");
+    assert_eq!(rotated.find_entity(entity.id()), Some(Location::new(1, 1)));
+    assert_eq!(rotated.entities_at(Location::new(1, 1)), &[entity]);
+    assert_eq!(rotated.rooms(), &[Rect::new(1, 0, 1, 2)]);
+
+    let flipped = world.flipped_horizontal();
+    assert_eq!(flipped.to_ascii(), "+.#\n ..\n");
+    assert_eq!(flipped.find_entity(entity.id()), Some(Location::new(1, 0)));
+    assert_eq!(world.flipped_vertical().to_ascii(), ".. \n#.+\n");
+
+    // Four quarter turns, or two flips, get back where we started.
+    let around = rotated.rotated_90().rotated_90().rotated_90();
+    assert_eq!(around.to_ascii(), world.to_ascii());
+    assert_eq!(world.flipped_vertical().flipped_vertical().to_ascii(), world.to_ascii());
+}
+
+#[test]
+fn test_transforms_turn_ledges() {
+    let world = WorldMap::from_ascii("#.#\n#v#\n#.#").unwrap();
+    let rotated = world.rotated_90();
+    assert_eq!(rotated.to_ascii(), "###\n.{.\n###\n");
+    let (left, right) = (Location::new(0, 1), Location::new(2, 1));
+    assert!(rotated.find_path(right, left).is_some());
+    assert!(rotated.find_path(left, right).is_none());
+    assert_eq!(rotated.flipped_horizontal().to_ascii(), "###\n.}.\n###\n");
+    assert_eq!(world.flipped_vertical().to_ascii(), "#.#\n#^#\n#.#\n");
+    assert_eq!(world.flipped_horizontal().to_ascii(), world.to_ascii());
+}
+
+#[test]
+fn test_location_orthogonal() {
+    let neighbors: Vec<_> = Location::new(0, 3).orthogonal().collect();
+    assert_eq!(neighbors, vec![Location::new(-1, 3), Location::new(0, 2),
+                               Location::new(1, 3), Location::new(0, 4)]);
+}
+
+#[test]
+fn test_vary_floors() {
+    let config = WorldMapConfig {
+        floor_variety: vec![(Terrain::Floor, 7), (Terrain::Grass, 2), (Terrain::Water, 1)],
+        .. WorldMapConfig::default()
+    };
+    let mut rng = seeded_rng(9);
+    let (world, _) = WorldMap::generate_with_config(&mut rng, 80, 50, &config);
+    let count = |terrain| world.tiles().filter(|&(tile, _)| tile.terrain == terrain).count();
+    assert!(count(Terrain::Floor) > count(Terrain::Grass));
+    assert!(count(Terrain::Grass) > count(Terrain::Water));
+    assert!(count(Terrain::Water) > 0);
+    assert!(count(Terrain::Wall) > 0 && count(Terrain::Door) > 0);
+    assert_eq!(world.connected_regions().len(), 1);
+
+    // Only walkable terrain can be scattered over the floor.
+    let config = WorldMapConfig {
+        floor_variety: vec![(Terrain::Wall, 1)],
+        .. WorldMapConfig::default()
+    };
+    assert!(!config.is_valid());
+}
+
+#[test]
+fn test_carve_tunnel() {
+    let mut world = WorldMap::from_ascii("#####\n\
+                                      #+###\n\
+                                      #####\n").unwrap();
+    world.carve_tunnel(Location::new(3, 0), Location::new(1, 2));
+    assert_eq!(world.to_ascii(), "#...#\n\
+                                  #+###\n\
+                                  #.###\n");
+
+    world.carve_tunnel(Location::new(-5, 2), Location::new(9, 2));
+    assert_eq!(world.to_ascii(), "#...#\n\
+                                  #+###\n\
+                                  .....\n");
+    assert!(world.find_path(Location::new(3, 0), Location::new(4, 2)).is_some());
+
+    // Chasms aren't filled in.
+    let mut world = WorldMap::from_ascii("#_#").unwrap();
+    world.carve_tunnel(Location::new(0, 0), Location::new(2, 0));
+    assert_eq!(world.to_ascii(), "._.\n");
+}
+
+#[test]
+fn test_entity_components() {
+    struct Health(i32);
+
+    let mut world = WorldMap::new(2, 1);
+    let here = Location::new(0, 0);
+    let entity = world.spawn_entity(here);
+    let other = world.spawn_entity(here);
+    assert!(world.component::<Health>(entity.id()).is_none());
+    world.insert_component(entity.id(), Health(10));
+    world.insert_component(other.id(), Health(5));
+    world.insert_component(entity.id(), "goblin");
+    world.component_mut::<Health>(entity.id()).unwrap().0 -= 4;
+    assert_eq!(world.component::<Health>(entity.id()).unwrap().0, 6);
+    assert_eq!(world.components::<Health>().unwrap().len(), 2);
+
+    // Removing the entity takes its components with it.
+    assert!(world.remove_entity(here, entity.id()));
+    assert!(world.component::<Health>(entity.id()).is_none());
+    assert!(world.component::<&str>(entity.id()).is_none());
+    assert_eq!(world.component::<Health>(other.id()).unwrap().0, 5);
+    assert_eq!(world.remove_component::<Health>(other.id()).map(|health| health.0), Some(5));
+}
+
+#[test]
+fn test_random_walkable() {
+    let mut world = WorldMap::from_ascii("#..#.\n").unwrap();
+    world.spawn_item(Location::new(4, 0));
+    let mut rng = seeded_rng(1);
+    let mut picked = HashSet::new();
+    for _ in 0..100 {
+        picked.insert(world.random_walkable(&mut rng).unwrap());
+    }
+    let expected: HashSet<_> = [Location::new(1, 0), Location::new(2, 0)].iter().cloned().collect();
+    assert_eq!(picked, expected);
+    assert!(WorldMap::new(3, 3).random_walkable(&mut rng).is_none());
+}
+
+#[test]
+fn test_generate_with_prefabs() {
+    use prefab::Prefab;
+
+    let config = WorldMapConfig {
+        prefabs: vec![Prefab::from_ascii("#####\n#...#\n#.:.#\n#...#\n#####")],
+        prefab_chance: 1.0,
+        .. WorldMapConfig::default()
+    };
+    let mut rng = seeded_rng(6);
+    let (world, _) = WorldMap::generate_with_config(&mut rng, 80, 50, &config);
+    assert!(world.rooms().len() > 1);
+    for room in world.rooms() {
+        assert_eq!((room.width, room.height), (5, 5));
+        assert_eq!(world.get_tile(room.center()).terrain, Terrain::Rubble);
+    }
+    assert_eq!(world.connected_regions().len(), 1);
+}
+
+#[test]
+fn test_terrain_at() {
+    let world = WorldMap::from_ascii("#.\n+#").unwrap();
+    assert_eq!(world.terrain_at(Location::new(1, 0)), Some(Terrain::Floor));
+    assert_eq!(world.terrain_at(Location::new(0, 1)), Some(Terrain::Door));
+    assert_eq!(world.terrain_at(Location::new(2, 0)), None);
+    assert_eq!(world.terrain_at(Location::new(0, -1)), None);
+}
+
+#[test]
+fn test_set_terrain() {
+    let mut world = WorldMap::new(3, 3);
+    assert!(world.set_terrain(Location::new(2, 2), Terrain::Water));
+    assert_eq!(world.terrain_at(Location::new(2, 2)), Some(Terrain::Water));
+    assert!(!world.set_terrain(Location::new(3, 0), Terrain::Water));
+    assert!(!world.set_terrain(Location::new(-1, 1), Terrain::Water));
+    assert_eq!(world.tiles().filter(|&(tile, _)| tile.terrain == Terrain::Water).count(), 1);
+}
+
+#[test]
+fn test_random_n() {
+    let mut rng = seeded_rng(4);
+    let mut picked = (0..10).random_n(&mut rng, 4);
+    assert_eq!(picked.len(), 4);
+    picked.sort();
+    picked.dedup();
+    assert_eq!(picked.len(), 4);
+    assert!(picked.iter().all(|&n| n >= 0 && n < 10));
+
+    let mut all = (0..3).random_n(&mut rng, 5);
+    all.sort();
+    assert_eq!(all, vec![0, 1, 2]);
+    assert!((0..0).random_n(&mut rng, 2).is_empty());
+    assert!((0..5).random_n(&mut rng, 0).is_empty());
+}
+
+#[test]
+fn test_checked_location() {
+    let world = WorldMap::new(4, 3);
+    assert_eq!(world.location(0, 0), Some(Location::new(0, 0)));
+    assert_eq!(world.location(3, 2), Some(Location::new(3, 2)));
+    assert_eq!(world.location(4, 2), None);
+    assert_eq!(world.location(3, 3), None);
+    assert_eq!(world.location(-1, 0), None);
+}
+
+#[test]
+fn test_generate_with_shared_walls() {
+    let config = WorldMapConfig {
+        wall_mode: WallMode::Shared,
+        room_shapes: vec![(RoomShape::Rectangular, 1)],
+        max_rooms: 40,
+        .. WorldMapConfig::default()
+    };
+    assert!(!WorldMapConfig { room_padding: 1, .. config.clone() }.is_valid());
+
+    let mut rng = seeded_rng(12);
+    let (world, _) = WorldMap::generate_with_config(&mut rng, 60, 40, &config);
+    assert_eq!(world.connected_regions().len(), 1);
+    // Somewhere two rooms were built against each other.
+    let rooms = world.rooms();
+    assert!(rooms.iter().enumerate().any(|(i, a)| rooms[i + 1..].iter()
+        .any(|b| a.intersects(b))));
+}
+
+#[test]
+fn test_validate() {
+    let mut rng = seeded_rng(2);
+    let (mut world, start) = WorldMap::generate(&mut rng, 60, 40);
+    assert_eq!(world.validate_connected(), Ok(()));
+
+    let entity = world.spawn_entity(start);
+    let elsewhere = world.nearest(start, |tile| tile.entities.is_empty()).unwrap();
+    world.get_tile_mut(elsewhere).entities.push(entity);
+    world.tiles[0].entities.push(Entity {id: 100, blocks: false});
+    let errors = world.validate().unwrap_err();
+    assert_eq!(errors.len(), 4);
+    assert!(errors[0].starts_with("Entity 100"));
+
+    // Two rooms with a wall between them.
+    let world = WorldMap::from_ascii("#####\n#.#.#\n#####").unwrap();
+    assert_eq!(world.validate(), Ok(()));
+    assert_eq!(world.validate_connected().unwrap_err().len(), 1);
+}
+
+#[test]
+fn test_find_path_bounded() {
+    let row = ::std::iter::repeat('.').take(100).collect::<String>();
+    let mut world = WorldMap::from_ascii(&vec![row; 100].join("\n")).unwrap();
+    // Wall off the far corner.
+    let corner = Location::new(99, 99);
+    world.set_terrain(Location::new(98, 99), Terrain::Wall);
+    world.set_terrain(Location::new(99, 98), Terrain::Wall);
+
+    let from = Location::new(0, 0);
+    assert!(world.find_path_bounded(from, corner, 500).is_none());
+    let near = Location::new(5, 3);
+    assert_eq!(world.find_path_bounded(from, near, 500).unwrap().len(), 9);
+    assert!(world.find_path_bounded(from, near, 3).is_none());
+}
+
+#[test]
+fn test_tags() {
+    let mut world = WorldMap::new(10, 10);
+    world.tag_region(&[Location::new(5, 5), Location::new(1, 6), Location::new(10, 0)], "arena");
+    world.tag_region(&[Location::new(2, 2), Location::new(5, 5)], "arena");
+    world.tag_region(&[Location::new(0, 0)], "exit");
+    assert_eq!(world.tiles_with_tag("arena"),
+               vec![Location::new(2, 2), Location::new(5, 5), Location::new(1, 6)]);
+    assert_eq!(world.tiles_with_tag("exit"), vec![Location::new(0, 0)]);
+    assert!(world.tiles_with_tag("treasure").is_empty());
+
+    let flipped = world.flipped_horizontal();
+    assert_eq!(flipped.tiles_with_tag("exit"), vec![Location::new(9, 0)]);
+}
+
+#[test]
+fn test_downsample() {
+    let world = WorldMap::from_ascii("#..:.\n\
+                                      ..::.\n\
+                                      ...  \n").unwrap();
+    assert_eq!(world.downsample(2), vec![
+        vec![Terrain::Wall, Terrain::Rubble, Terrain::Floor],
+        vec![Terrain::Floor, Terrain::Floor, Terrain::Nothing]
+    ]);
+    assert_eq!(world.downsample(5), vec![vec![Terrain::Wall]]);
+    assert_eq!(world.downsample(1).len(), 3);
+    assert_eq!(world.downsample(1)[1][2], Terrain::Rubble);
+
+    let any_rubble = world.downsample_with(3, |block| {
+        if block.contains(&Terrain::Rubble) { Terrain::Rubble } else { Terrain::Floor }
+    });
+    assert_eq!(any_rubble, vec![vec![Terrain::Rubble, Terrain::Rubble]]);
+}
+
+#[test]
+fn test_feature_center_and_random_floor() {
+    for &(width, height) in [(3, 3), (4, 4), (5, 8), (6, 3)].iter() {
+        let room = FeatureBuilder::room(width, height).location(Location::new(7, 2)).build();
+        let center = room.center();
+        assert!(room.floors().any(|&floor| floor == center),
+                "{}x{} center {:?} isn't floor", width, height, center);
+    }
+    let circle = FeatureBuilder::room_circle(4).location(Location::new(10, 10)).build();
+    assert!(circle.floors().any(|&floor| floor == circle.center()));
+
+    let mut rng = seeded_rng(1);
+    let room = FeatureBuilder::room(5, 4).build();
+    for _ in 0..20 {
+        let floor = room.random_floor(&mut rng);
+        assert!(room.floors().any(|&other| other == floor));
+    }
+}
+
+#[test]
+fn test_connect_rooms_via_centers() {
+    let config = WorldMapConfig {
+        corridor_target: CorridorTarget::Centers,
+        corridor_count: 4,
+        .. WorldMapConfig::default()
+    };
+    let mut rng = seeded_rng(8);
+    let (world, _) = WorldMap::generate_with_config(&mut rng, 80, 50, &config);
+    assert_eq!(world.validate_connected(), Ok(()));
+
+    // Doors are gone straight through, never entered from the side.
+    let walkable = |loc: Location| world.terrain_at(loc).map_or(false, |t| t.is_walkable());
+    let doors: Vec<_> = world.tiles()
+        .filter(|&(tile, _)| tile.terrain == Terrain::Door)
+        .map(|(_, loc)| loc)
+        .collect();
+    assert!(doors.len() > 0);
+    for door in doors {
+        let (x, y) = (door.x, door.y);
+        let across = walkable(Location::new(x - 1, y)) && walkable(Location::new(x + 1, y));
+        let down = walkable(Location::new(x, y - 1)) && walkable(Location::new(x, y + 1));
+        assert!(across || down, "Door at {:?} isn't in a wall", door);
+    }
+}
+
+#[test]
+fn test_fingerprint() {
+    let (world, start) = WorldMap::generate(&mut seeded_rng(5), 60, 40);
+    let (again, _) = WorldMap::generate(&mut seeded_rng(5), 60, 40);
+    assert_eq!(world.fingerprint(), again.fingerprint());
+    let (other, _) = WorldMap::generate(&mut seeded_rng(6), 60, 40);
+    assert!(world.fingerprint() != other.fingerprint());
+
+    let mut changed = WorldMap::from_rle_bytes(&world.to_rle_bytes()).unwrap();
+    assert_eq!(changed.fingerprint(), world.fingerprint());
+    changed.spawn_item(start);
+    assert!(changed.fingerprint() != world.fingerprint());
+
+    // Same tiles, different shape.
+    let wide = WorldMap::from_ascii("....").unwrap();
+    let tall = WorldMap::from_ascii("..\n..").unwrap();
+    assert!(wide.fingerprint() != tall.fingerprint());
+    assert_eq!(WorldMap::new(1, 1).fingerprint(), 0xad96c741d467807d);
+
+    // Counts past a byte aren't truncated.
+    let mut crowded = WorldMap::from_ascii(".").unwrap();
+    let mut heap = WorldMap::from_ascii(".").unwrap();
+    for _ in 0..256 {
+        crowded.spawn_item(Location::new(0, 0));
+    }
+    for _ in 0..257 {
+        heap.spawn_item(Location::new(0, 0));
+    }
+    assert!(crowded.fingerprint() != WorldMap::from_ascii(".").unwrap().fingerprint());
+    assert!(crowded.fingerprint() != heap.fingerprint());
+}
+
+#[test]
+fn test_prune_unreachable() {
+    let mut world = WorldMap::from_ascii("#### ####\n\
+                                      #..# #..+\n\
+                                      #### ####\n").unwrap();
+    world.add_room(Rect::new(0, 0, 4, 3));
+    world.add_room(Rect::new(5, 0, 4, 3));
+    world.prune_unreachable(Location::new(1, 1));
+    assert_eq!(world.to_ascii(), "####     \n#..#     \n####     \n");
+    assert_eq!(world.rooms(), &[Rect::new(0, 0, 4, 3)]);
+}
+
+#[test]
+fn test_generated_floor_all_reachable() {
+    let config = WorldMapConfig { room_shapes: vec![(RoomShape::Rectangular, 1),
+                                                    (RoomShape::Elliptical, 1)],
+                                  corridor_style: CorridorStyle::Drunken,
+                                  .. WorldMapConfig::default() };
+    for seed in 0..5 {
+        let (world, start) = WorldMap::generate_with_config(&mut seeded_rng(seed), 70, 45, &config);
+        let reached = world.flood(start, |loc| world.get_tile(loc).terrain.is_walkable());
+        let walkable = world.tiles().filter(|&(tile, _)| tile.terrain.is_walkable()).count();
+        assert_eq!(reached.len(), walkable);
+    }
+}
+
+#[test]
+fn test_entities() {
+    let mut world = WorldMap::from_ascii("...\n...").unwrap();
+    assert_eq!(world.entities().count(), 0);
+    let a = world.spawn_entity(Location::new(2, 1));
+    let b = world.spawn_entity(Location::new(1, 0));
+    let c = world.spawn_item(Location::new(2, 1));
+    let entities: Vec<_> = world.entities().collect();
+    assert_eq!(entities, vec![(b, Location::new(1, 0)), (a, Location::new(2, 1)),
+                              (c, Location::new(2, 1))]);
+}
+
+#[test]
+fn test_feature_wall_thickness() {
+    let room = FeatureBuilder::room(7, 5).wall_thickness(2).build();
+    let floors: Vec<_> = room.floors().cloned().collect();
+    assert_eq!(floors, vec![Location::new(2, 2), Location::new(3, 2), Location::new(4, 2)]);
+    assert_eq!(room.walls().count(), 32);
+    assert_eq!(FeatureBuilder::room(7, 5).wall_thickness(1).build().floors().count(), 15);
+    assert_eq!(FeatureBuilder::room(4, 6).wall_thickness(2).build().floors().count(), 0);
+}
+
+#[test]
+fn test_generate_with_thick_walls() {
+    let config = WorldMapConfig {
+        wall_thickness: 2,
+        room_shapes: vec![(RoomShape::Rectangular, 1)],
+        .. WorldMapConfig::default()
+    };
+    assert!(!WorldMapConfig { max_room_size: 4, .. config.clone() }.is_valid());
+
+    let mut rng = seeded_rng(4);
+    let (world, start) = WorldMap::generate_with_config(&mut rng, 80, 50, &config);
+    assert!(world.rooms().len() > 1);
+    assert_eq!(world.validate_connected(), Ok(()));
+    assert!(world.get_tile(start).terrain.is_walkable());
+    for room in world.rooms() {
+        // The inner corners are wall too.
+        let inner = Location::new(room.x + 1, room.y + room.height - 2);
+        assert_eq!(world.get_tile(inner).terrain, Terrain::Wall);
+    }
+}
+
+#[test]
+fn test_carve_room() {
+    let mut world = WorldMap::new(6, 5);
+    world.carve_room(Rect::new(1, 1, 4, 3));
+    world.carve_room(Rect::new(4, -1, 3, 3));
+    world.carve_room(Rect::new(10, 10, 3, 3));
+    let rows = ["    #.", " #####", " #..# ", " #### ", "      "];
+    assert_eq!(world.to_ascii(), rows.join("\n") + "\n");
+    assert_eq!(world.rooms(), &[Rect::new(1, 1, 4, 3), Rect::new(4, 0, 2, 2)]);
+
+    let mut world = WorldMap::new(3, 4);
+    world.carve_room(Rect::new(-1000000, 0, 2000000, 3));
+    assert_eq!(world.to_ascii(), "###\n...\n###\n   \n");
+}
+
+#[test]
+fn test_reachable() {
+    let mut world = WorldMap::from_ascii("#######\n\
+                                      #..:..#\n\
+                                      #.###.#\n\
+                                      #######").unwrap();
+    let from = Location::new(1, 1);
+    let range = world.reachable(from, 4, false);
+    assert_eq!(range.len(), 4);
+    assert_eq!(range.get(&from), Some(&0));
+    assert_eq!(range.get(&Location::new(1, 2)), Some(&1));
+    assert_eq!(range.get(&Location::new(3, 1)), Some(&4));
+    assert_eq!(range.get(&Location::new(4, 1)), None);
+    assert_eq!(world.reachable(from, 7, false).get(&Location::new(5, 2)), Some(&7));
+
+    // A monster in the way.
+    world.spawn_entity(Location::new(2, 1));
+    world.spawn_item(Location::new(1, 2));
+    let range = world.reachable(from, 10, false);
+    assert_eq!(range.len(), 2);
+    assert!(range.contains_key(&Location::new(1, 2)));
+    let range = world.reachable(from, 10, true);
+    assert!(!range.contains_key(&Location::new(2, 1)));
+    assert_eq!(range.get(&Location::new(4, 1)), Some(&5));
+}
+
+#[test]
+fn test_config_presets() {
+    let presets = [WorldMapConfig::dense_cavern(), WorldMapConfig::sparse_dungeon(),
+                   WorldMapConfig::tight_maze()];
+    for config in presets.iter() {
+        assert!(config.is_valid());
+        let (world, start) = WorldMap::generate_with_config(&mut seeded_rng(2), 80, 50, config);
+        assert!(world.rooms().len() > 1);
+        assert!(world.get_tile(start).terrain.is_walkable());
+        assert_eq!(world.validate_connected(), Ok(()));
+    }
+}
+
+#[test]
+fn test_free_floors_in() {
+    let mut world = WorldMap::from_ascii("#####\n\
+                                      #..:#\n\
+                                      #...#\n\
+                                      #####").unwrap();
+    let room = Rect::new(0, 0, 5, 4);
+    assert_eq!(world.free_floors_in(room).len(), 5);
+    world.spawn_item(Location::new(2, 1));
+    let free = world.free_floors_in(room);
+    assert_eq!(free, vec![Location::new(1, 1), Location::new(1, 2), Location::new(2, 2),
+                          Location::new(3, 2)]);
+    assert_eq!(world.free_floors_in(Rect::new(2, 2, 10, 10)),
+               vec![Location::new(2, 2), Location::new(3, 2)]);
+}
+
+#[test]
+fn test_world_map_debug() {
+    let world = WorldMap::from_ascii("#.#\n+ ~").unwrap();
+    assert_eq!(format!("{:?}", world), "WorldMap 3x2\n#.#\n+ ~\n");
+}
+
+#[test]
+fn test_room_graph() {
+    let mut world = WorldMap::from_ascii("####   ####    ####\n\
+                                      #..+...+..#    #..#\n\
+                                      ####   ####    ####").unwrap();
+    world.add_room(Rect::new(0, 0, 4, 3));
+    world.add_room(Rect::new(7, 0, 4, 3));
+    world.add_room(Rect::new(15, 0, 4, 3));
+    assert_eq!(world.room_graph(), vec![(0, 1)]);
+
+    let mut rng = seeded_rng(1);
+    let (world, _) = WorldMap::generate(&mut rng, 80, 50);
+    let edges = world.room_graph();
+    assert!(edges.len() >= world.rooms().len() - 1);
+    assert!(edges.iter().all(|&(i, j)| i < j && j < world.rooms().len()));
+}
+
+#[test]
+fn test_min_rooms() {
+    let config = WorldMapConfig {
+        max_rooms: 10,
+        min_rooms: 10,
+        min_room_size: 6,
+        max_room_size: 6,
+        room_padding: 6,
+        room_shapes: vec![(RoomShape::Rectangular, 1)],
+        .. WorldMapConfig::default()
+    };
+    // Ten rooms can't fit that far apart, but they can closer together.
+    let spread = WorldMapConfig { min_rooms: 0, .. config.clone() };
+    let (world, _) = WorldMap::try_generate_with_config(&mut seeded_rng(1), 40, 30, &spread)
+        .unwrap();
+    assert!(world.rooms().len() < 10);
+    let (world, _) = WorldMap::try_generate_with_config(&mut seeded_rng(1), 40, 30, &config)
+        .unwrap();
+    assert_eq!(world.rooms().len(), 10);
+
+    let config = WorldMapConfig { max_rooms: 30, min_rooms: 30, .. config };
+    assert_eq!(WorldMap::try_generate_with_config(&mut seeded_rng(1), 30, 30, &config).err(),
+               Some(GenError::TooFewRooms));
+    assert!(!WorldMapConfig { min_rooms: 31, .. config }.is_valid());
+}
+
+#[test]
+fn test_flood_fill() {
+    let mut world = WorldMap::from_ascii("...#.\n\
+                                      .#.#.\n\
+                                      #..#.").unwrap();
+    world.flood_fill(Location::new(0, 0), Terrain::Water);
+    assert_eq!(world.to_ascii(), "~~~#.\n~#~#.\n#~~#.\n");
+    world.flood_fill(Location::new(1, 0), Terrain::Water);
+    world.flood_fill(Location::new(9, 9), Terrain::Grass);
+    assert_eq!(world.to_ascii(), "~~~#.\n~#~#.\n#~~#.\n");
+    world.flood_fill(Location::new(3, 2), Terrain::Floor);
+    assert_eq!(world.to_ascii(), "~~~..\n~#~..\n#~~..\n");
+}
+
+#[test]
+fn test_ledge_is_one_way() {
+    let mut world = WorldMap::from_ascii("#.#\n#v#\n#.#").unwrap();
+    let top = Location::new(1, 0);
+    let ledge = Location::new(1, 1);
+    let bottom = Location::new(1, 2);
+    assert_eq!(world.find_path(top, bottom), Some(vec![top, ledge, bottom]));
+    assert_eq!(world.find_path(bottom, top), None);
+    assert_eq!(world.find_path_with_movement(bottom, top, Movement::Diagonal), None);
+    assert!(!world.reachable(bottom, 10, false).contains_key(&ledge));
+
+    let climber = world.spawn_entity(bottom);
+    assert_eq!(world.move_entity(climber.id(), bottom, ledge), Err(MoveError::WrongWay));
+    let faller = world.spawn_entity(top);
+    assert_eq!(world.move_entity(faller.id(), top, ledge), Ok(()));
+
+    assert!(world.can_step(top, ledge));
+    assert!(!world.can_step(bottom, ledge));
+    assert!(world.can_step(ledge, bottom));
+    assert!(!world.can_step(top, Location::new(0, 0)));
+    assert!(!world.can_step(bottom, Location::new(1, 3)));
+}
+
+#[test]
+fn test_populate_with_safe_zone() {
+    let mut rng = seeded_rng(4);
+    let mut world = WorldMap::from_ascii(".......\n.......\n.......\n.......\n.......").unwrap();
+    world.add_room(Rect::new(0, 0, 7, 5));
+    let start = Location::new(3, 2);
+    let spawned = world.populate_with_safe_zone(&mut rng, 30..31, start, 2);
+    // Only the two outer columns are far enough away.
+    assert_eq!(spawned.len(), 10);
+    for (_, loc) in world.entities() {
+        assert!(loc.chebyshev(&start) > 2);
+    }
+}
+
+#[test]
+fn test_take_dirty() {
+    let mut world = WorldMap::from_ascii("....\n....").unwrap();
+    world.set_terrain(Location::new(0, 0), Terrain::Wall);
+    assert!(world.take_dirty().is_empty());
+
+    world.track_dirty(true);
+    let entity = world.spawn_entity(Location::new(1, 1));
+    world.take_dirty();
+    world.set_terrain(Location::new(3, 0), Terrain::Wall);
+    world.move_entity(entity.id(), Location::new(1, 1), Location::new(2, 1)).unwrap();
+    assert_eq!(world.take_dirty(),
+               vec![Location::new(3, 0), Location::new(1, 1), Location::new(2, 1)]);
+    assert!(world.take_dirty().is_empty());
+
+    // Moves that fail and writes that change nothing leave no mark.
+    let other = world.spawn_entity(Location::new(3, 1));
+    world.set_terrain(Location::new(2, 0), Terrain::Wall);
+    world.take_dirty();
+    let at = Location::new(2, 1);
+    assert_eq!(world.move_entity(entity.id(), at, Location::new(3, 1)), Err(MoveError::Blocked));
+    assert_eq!(world.move_entity(entity.id(), at, Location::new(2, 0)),
+               Err(MoveError::NotWalkable));
+    assert_eq!(world.move_entity(other.id(), at, Location::new(1, 1)), Err(MoveError::NotFound));
+    assert!(!world.remove_entity(Location::new(0, 1), entity.id()));
+    world.set_terrain(Location::new(3, 0), Terrain::Wall);
+    assert!(world.take_dirty().is_empty());
+    world.update_visibility(at, 0);
+    world.take_dirty();
+    world.update_visibility(at, 0);
+    assert!(world.take_dirty().is_empty());
+
+    world.set_terrain(Location::new(3, 1), Terrain::Wall);
+    world.track_dirty(false);
+    assert!(world.take_dirty().is_empty());
+}
+
+#[test]
+fn test_remove_dead_ends() {
+    let ascii = "#######\n\
+                 #.....#\n\
+                 #.###.#\n\
+                 #.....#\n\
+                 ###.###\n\
+                 ###.###\n\
+                 #######";
+    let mut world = WorldMap::from_ascii(ascii).unwrap();
+    world.remove_dead_ends(1);
+    assert_eq!(world.terrain_at(Location::new(3, 5)), Some(Terrain::Wall));
+    assert_eq!(world.terrain_at(Location::new(3, 4)), Some(Terrain::Floor));
+
+    world.remove_dead_ends(5);
+    assert_eq!(world.terrain_at(Location::new(3, 4)), Some(Terrain::Wall));
+    // The loop has no ends, so all of it survives.
+    let floors = world.tiles().filter(|&(tile, _)| tile.terrain == Terrain::Floor).count();
+    assert_eq!(floors, 12);
+}
+
+#[test]
+fn test_size_distributions() {
+    use config::SizeDistribution;
+
+    let mut rng = seeded_rng(6);
+    let mean = |rng: &mut StdRng, sizes: SizeDistribution| {
+        let samples: Vec<_> = (0..2000).map(|_| sizes.sample(rng, 3, 15)).collect();
+        assert!(samples.iter().all(|&size| size >= 3 && size <= 15));
+        samples.iter().sum::<i32>() as f64 / samples.len() as f64
+    };
+    let uniform = mean(&mut rng, SizeDistribution::Uniform);
+    assert!((uniform - 9.0).abs() < 0.5);
+    assert!(mean(&mut rng, SizeDistribution::SmallBiased) < uniform - 1.5);
+    assert!(mean(&mut rng, SizeDistribution::Bimodal) < uniform - 1.5);
+    assert!((0..200).any(|_| SizeDistribution::Bimodal.sample(&mut rng, 3, 15) >= 12));
+}
+
+#[test]
+fn test_same_room() {
+    let mut world = WorldMap::from_ascii("#####   #####\n\
+                                          #...#####...#\n\
+                                          #...+...+...#\n\
+                                          #############").unwrap();
+    world.add_room(Rect::new(0, 0, 5, 4));
+    world.add_room(Rect::new(8, 0, 5, 4));
+    assert!(world.same_room(Location::new(1, 1), Location::new(3, 2)));
+    assert!(world.same_room(Location::new(1, 1), Location::new(1, 1)));
+    assert!(!world.same_room(Location::new(1, 1), Location::new(9, 1)));
+
+    let corridor = Location::new(6, 2);
+    assert!(!world.same_room(corridor, corridor));
+    assert!(!world.same_room(corridor, Location::new(1, 1)));
+}
+
+#[test]
+fn test_generate_with_scripted_rng() {
+    use scripted_rng::ScriptedRng;
+
+    let config = WorldMapConfig { max_rooms: 1, .. WorldMapConfig::default() };
+    let mut rng = ScriptedRng::new(&[0, 2, 1, 10, 7]);
+    let (world, start) = WorldMap::generate_with_config(&mut rng, 30, 20, &config);
+    assert_eq!(world.rooms(), &[Rect::new(10, 7, 5, 4)]);
+    assert!(Rect::new(11, 8, 3, 2).contains(start));
+}
+
+#[test]
+fn test_content_bounds() {
+    let mut world = WorldMap::new(20, 10);
+    assert_eq!(world.content_bounds(), None);
+    world.carve_room(Rect::new(6, 3, 5, 4));
+    world.set_terrain(Location::new(12, 2), Terrain::Wall);
+    assert_eq!(world.content_bounds(), Some(Rect::new(6, 2, 7, 5)));
+}
+
+#[test]
+fn test_crop() {
+    let mut world = WorldMap::from_ascii("#####\n\
+                                          #.<.#\n\
+                                          #...#\n\
+                                          #####").unwrap();
+    world.add_room(Rect::new(0, 0, 5, 4));
+    let kept = world.spawn_entity(Location::new(3, 2));
+    world.spawn_entity(Location::new(1, 1));
+    world.tag_region(&[Location::new(2, 2)], "altar");
+
+    let cropped = world.crop(Rect::new(2, 1, 4, 2)).unwrap();
+    assert_eq!(cropped.width(), 4);
+    assert_eq!(cropped.height(), 2);
+    assert_eq!(cropped.terrain_at(Location::new(0, 0)), Some(Terrain::StairsUp));
+    assert_eq!(cropped.terrain_at(Location::new(3, 0)), Some(Terrain::Nothing));
+    assert_eq!(cropped.find_entity(kept.id()), Some(Location::new(1, 1)));
+    assert_eq!(cropped.entities().count(), 1);
+    assert_eq!(cropped.rooms(), &[Rect::new(0, 0, 3, 2)]);
+    assert_eq!(cropped.tiles_with_tag("altar"), vec![Location::new(0, 1)]);
+    assert_eq!(cropped.validate(), Ok(()));
+
+    assert!(world.crop(Rect::new(2, 1, 0, 2)).is_none());
+    assert!(world.crop(Rect::new(2, 1, 3, -1)).is_none());
+    assert!(world.crop(Rect::new(10, 10, 3, 3).clamp_to(5, 4)).is_none());
+}
+
+#[test]
+fn test_remove_dead_ends_keeps_rooms() {
+    let ascii = "#####\n\
+                 #...#\n\
+                 ##+##\n\
+                 ##.##\n\
+                 ##.##\n\
+                 ##+##\n\
+                 ##.##\n\
+                 #####";
+    let mut world = WorldMap::from_ascii(ascii).unwrap();
+    world.add_room(Rect::new(0, 0, 5, 3));
+    // A one-tile closet behind a door, at the end of the corridor.
+    world.add_room(Rect::new(1, 5, 3, 3));
+    world.remove_dead_ends(10);
+    assert_eq!(world.to_ascii(), ascii.to_string() + "\n");
 }